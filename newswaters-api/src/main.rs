@@ -14,7 +14,7 @@ use serde::{Deserialize, Serialize};
 use service::search_engine;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::repository::Repository;
+use crate::repository::{DuplicateItem, Repository, ThreadItem};
 use crate::service::inference;
 
 #[derive(Clone)]
@@ -55,7 +55,10 @@ async fn main() -> Result<()> {
             &prefix,
             Router::new()
                 .route("/healthz", routing::get(|| async { "Ok" }))
-                .route("/search-similar-items", routing::post(search_similar_items)),
+                .route("/search-similar-items", routing::post(search_similar_items))
+                .route("/find-thread", routing::post(find_thread))
+                .route("/find-duplicates", routing::post(find_duplicates))
+                .route("/find-summary", routing::post(find_summary)),
         )
         .layer(cors)
         .with_state(state);
@@ -104,3 +107,61 @@ async fn search_similar_items(
     let response = SearchSimilarItemsResponse { items };
     Ok(Json(response))
 }
+
+#[derive(Deserialize)]
+struct FindThreadRequest {
+    id: i32,
+}
+
+#[derive(Serialize)]
+struct FindThreadResponse {
+    items: Vec<ThreadItem>,
+}
+
+async fn find_thread(
+    State(state): State<AppState>,
+    Json(payload): Json<FindThreadRequest>,
+) -> Result<Json<FindThreadResponse>, AppError> {
+    let items = state.repo.find_thread(payload.id)?;
+    let response = FindThreadResponse { items };
+    Ok(Json(response))
+}
+
+#[derive(Deserialize)]
+struct FindDuplicatesRequest {
+    id: i32,
+}
+
+#[derive(Serialize)]
+struct FindDuplicatesResponse {
+    items: Vec<DuplicateItem>,
+}
+
+async fn find_duplicates(
+    State(state): State<AppState>,
+    Json(payload): Json<FindDuplicatesRequest>,
+) -> Result<Json<FindDuplicatesResponse>, AppError> {
+    let items = state.repo.find_duplicates(payload.id)?;
+    let response = FindDuplicatesResponse { items };
+    Ok(Json(response))
+}
+
+#[derive(Deserialize)]
+struct FindSummaryRequest {
+    id: i32,
+}
+
+#[derive(Serialize)]
+struct FindSummaryResponse {
+    topics: Option<Vec<String>>,
+    summary: Option<String>,
+}
+
+async fn find_summary(
+    State(state): State<AppState>,
+    Json(payload): Json<FindSummaryRequest>,
+) -> Result<Json<FindSummaryResponse>, AppError> {
+    let (topics, summary) = state.repo.find_summary(payload.id)?;
+    let response = FindSummaryResponse { topics, summary };
+    Ok(Json(response))
+}
@@ -6,19 +6,29 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
 use diesel::{prelude::*, sql_types::*};
 
+// The id, depth, author, text and time of a comment, or of the story at depth 0
+pub(crate) type ThreadItem = (i32, i32, Option<String>, Option<String>, Option<i64>);
+
+// The id, title, url and time of a story linking the same page
+pub(crate) type DuplicateItem = (i32, Option<String>, Option<String>, Option<i64>);
+
 pub(crate) struct Repository {
     pool: Pool<ConnectionManager<PgConnection>>,
 }
 
 impl Repository {
     pub(crate) fn new() -> Result<Self> {
+        Self::connect(&env::var("DATABASE_DB")?)
+    }
+
+    pub(crate) fn connect(database: &str) -> Result<Self> {
         let database_url = format!(
             "postgres://{}:{}@{}:{}/{}",
             env::var("DATABASE_USER")?,
             env::var("DATABASE_PASSWORD")?,
             env::var("DATABASE_HOST")?,
             env::var("DATABASE_PORT").unwrap_or("5432".to_string()),
-            env::var("DATABASE_DB")?,
+            database,
         );
         let pool = Pool::builder()
             .test_on_check_out(true)
@@ -30,18 +40,71 @@ impl Repository {
         &self,
         ids: &[i32],
     ) -> Result<HashMap<i32, (Option<String>, Option<String>, Option<i64>)>> {
-        let items_map = diesel::sql_query(format!(
+        let items_map = diesel::sql_query(
             "SELECT id, title, url, time \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+            FROM unnest($1) AS s(i) \
             JOIN items ON s.i = items.id",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
+        )
+        .bind::<Array<Integer>, _>(ids)
         .get_results::<ItemRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, (r.title, r.url, r.time)))
         .collect();
         Ok(items_map)
     }
+
+    pub(crate) fn find_thread(&self, story_id: i32) -> Result<Vec<ThreadItem>> {
+        // Follows `kids` arrays recursively, sorting by the path of positions to keep the display order
+        let thread = diesel::sql_query(
+            "WITH RECURSIVE thread(id, kids, depth, path) AS ( \
+                SELECT id, kids, 0, ARRAY[]::bigint[] FROM items WHERE id = $1 \
+                UNION ALL \
+                SELECT items.id, items.kids, thread.depth + 1, thread.path || k.position \
+                FROM thread \
+                CROSS JOIN LATERAL unnest(thread.kids) WITH ORDINALITY AS k(kid, position) \
+                JOIN items ON k.kid = items.id \
+            ) \
+            SELECT thread.id, depth, items.by, items.text, items.time \
+            FROM thread \
+            JOIN items ON thread.id = items.id \
+            ORDER BY path ASC",
+        )
+        .bind::<Integer, _>(story_id)
+        .get_results::<ThreadItemRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.depth, r.by, r.text, r.time))
+        .collect();
+        Ok(thread)
+    }
+
+    pub(crate) fn find_duplicates(&self, id: i32) -> Result<Vec<DuplicateItem>> {
+        // The original item, i.e. the canonical story, comes first
+        let duplicates = diesel::sql_query(
+            "WITH original AS (SELECT COALESCE(duplicate_of, item_id) AS id FROM item_urls WHERE item_id = $1) \
+            SELECT items.id, items.title, url, time \
+            FROM item_urls \
+            JOIN items ON item_urls.item_id = items.id \
+            WHERE item_urls.item_id = (SELECT id FROM original) OR duplicate_of = (SELECT id FROM original) \
+            ORDER BY duplicate_of IS NOT NULL ASC, items.id ASC",
+        )
+        .bind::<Integer, _>(id)
+        .get_results::<ItemRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.title, r.url, r.time))
+        .collect();
+        Ok(duplicates)
+    }
+
+    pub(crate) fn find_summary(&self, id: i32) -> Result<(Option<Vec<String>>, Option<String>)> {
+        // Summaries made before topics were stored separately have no topics
+        let summary = diesel::sql_query("SELECT topics, summary FROM item_urls WHERE item_id = $1")
+            .bind::<Integer, _>(id)
+            .get_result::<SummaryRecord>(&mut self.pool.get()?)
+            .optional()?
+            .map(|r| (r.topics, r.summary))
+            .unwrap_or((None, None));
+        Ok(summary)
+    }
 }
 
 #[derive(QueryableByName)]
@@ -55,3 +118,114 @@ struct ItemRecord {
     #[diesel(sql_type = Nullable<Int8>)]
     time: Option<i64>,
 }
+
+#[derive(QueryableByName)]
+struct ThreadItemRecord {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Integer)]
+    depth: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    by: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    text: Option<String>,
+    #[diesel(sql_type = Nullable<Int8>)]
+    time: Option<i64>,
+}
+
+#[derive(QueryableByName)]
+struct SummaryRecord {
+    #[diesel(sql_type = Nullable<Array<Text>>)]
+    topics: Option<Vec<String>>,
+    #[diesel(sql_type = Nullable<Text>)]
+    summary: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tests run against the database named by `DATABASE_TEST_DB`, which is to be migrated beforehand,
+    // and are skipped without it
+    fn connect() -> Option<Repository> {
+        let database = env::var("DATABASE_TEST_DB").ok()?;
+        Some(Repository::connect(&database).unwrap())
+    }
+
+    fn insert_stories(repo: &Repository, ids: &[i32]) {
+        let conn = &mut repo.pool.get().unwrap();
+        diesel::sql_query("DELETE FROM item_urls WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+        diesel::sql_query("DELETE FROM items WHERE id = ANY($1)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+        diesel::sql_query(
+            "INSERT INTO items (id, type, title, url, time) \
+            SELECT i, 'story', 'Story ' || i, 'https://example.com/' || i, i FROM unnest($1) AS s(i)",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .execute(conn)
+        .unwrap();
+    }
+
+    #[test]
+    fn finds_items_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_600_000..1_900_620_000).collect();
+        insert_stories(&repo, &ids);
+        assert!(repo.find_items(&[]).unwrap().is_empty());
+        let items = repo.find_items(&ids).unwrap();
+        assert_eq!(items.len(), ids.len());
+        assert_eq!(items[&1_900_600_000].0.as_deref(), Some("Story 1900600000"));
+    }
+
+    #[test]
+    fn finds_duplicates_from_any_of_them() {
+        let Some(repo) = connect() else { return };
+        let ids = [1_900_700_000, 1_900_700_001, 1_900_700_002];
+        insert_stories(&repo, &ids);
+        diesel::sql_query(
+            "INSERT INTO item_urls (item_id, title, duplicate_of) \
+            VALUES ($1, 'Page', NULL), ($2, 'Page', $1), ($3, 'Page', $1)",
+        )
+        .bind::<Integer, _>(ids[0])
+        .bind::<Integer, _>(ids[1])
+        .bind::<Integer, _>(ids[2])
+        .execute(&mut repo.pool.get().unwrap())
+        .unwrap();
+        let duplicates = repo.find_duplicates(ids[2]).unwrap();
+        assert_eq!(duplicates.iter().map(|d| d.0).collect::<Vec<_>>(), ids);
+        assert_eq!(duplicates[0].1.as_deref(), Some("Story 1900700000"));
+    }
+
+    #[test]
+    fn finds_thread_in_display_order() {
+        let Some(repo) = connect() else { return };
+        let ids = [1_900_710_000, 1_900_710_001, 1_900_710_002, 1_900_710_003];
+        insert_stories(&repo, &ids);
+        // The story has two comments, the first of which has a reply
+        diesel::sql_query(
+            "UPDATE items SET type = 'comment', text = 'Comment ' || id, \
+            kids = CASE WHEN id = $2 THEN ARRAY[$4] END WHERE id = ANY($1)",
+        )
+        .bind::<Array<Integer>, _>(&ids[1..])
+        .bind::<Integer, _>(ids[1])
+        .bind::<Integer, _>(ids[2])
+        .bind::<Integer, _>(ids[3])
+        .execute(&mut repo.pool.get().unwrap())
+        .unwrap();
+        diesel::sql_query("UPDATE items SET kids = ARRAY[$2, $3] WHERE id = $1")
+            .bind::<Integer, _>(ids[0])
+            .bind::<Integer, _>(ids[1])
+            .bind::<Integer, _>(ids[2])
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        let thread = repo.find_thread(ids[0]).unwrap();
+        let order: Vec<(i32, i32)> = thread.iter().map(|t| (t.0, t.1)).collect();
+        assert_eq!(order, [(ids[0], 0), (ids[1], 1), (ids[3], 2), (ids[2], 1)]);
+        assert_eq!(thread[2].3.as_deref(), Some("Comment 1900710003"));
+    }
+}
@@ -14,6 +14,9 @@ const INSTRUCT_TEMPLATE: &str = "<s>[INST] {instruction} [/INST]";
 pub(crate) struct Llama {
     model_path: String,
     instruct_template: String,
+    pub(crate) model_name: String,
+    pub(crate) ctx_size: usize,
+    pub(crate) temperature: f32,
 }
 
 impl Llama {
@@ -36,10 +39,14 @@ impl Llama {
         return Ok(Self {
             model_path,
             instruct_template: INSTRUCT_TEMPLATE.to_string(),
+            model_name,
+            ctx_size: env::var("INFERENCE_CTX_SIZE").unwrap_or("8192".to_string()).parse()?,
+            temperature: env::var("INFERENCE_TEMP").unwrap_or("0.8".to_string()).parse()?,
         });
     }
 
-    pub(crate) async fn inference(&self, instruction: &str) -> Result<String> {
+    // Without a seed, a random one is used
+    pub(crate) async fn inference(&self, instruction: &str, seed: Option<u32>) -> Result<String> {
         let prompt = self.instruct_template.replace("{instruction}", instruction);
         let output = Command::new("llama")
             .args([
@@ -48,11 +55,13 @@ impl Llama {
                 "--threads",
                 &env::var("INFERENCE_THREADS").unwrap_or("4".to_string()),
                 "--ctx-size",
-                &env::var("INFERENCE_CTX_SIZE").unwrap_or("8192".to_string()),
+                &self.ctx_size.to_string(),
                 "--temp",
-                &env::var("INFERENCE_TEMP").unwrap_or("0.8".to_string()),
+                &self.temperature.to_string(),
                 "--repeat-penalty",
                 &env::var("INFERENCE_REPEAT_PENALTY").unwrap_or("1.2".to_string()),
+                "--seed",
+                &seed.map(|s| s.to_string()).unwrap_or("-1".to_string()),
                 "--prompt",
                 &prompt,
                 "--log-disable",
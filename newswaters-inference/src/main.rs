@@ -47,6 +47,7 @@ async fn main() -> Result<()> {
     println!("[INFO] main.initialize");
     let app = Router::new()
         .route("/healthz", routing::get(|| async { "Ok" }))
+        .route("/context", routing::get(context))
         .route("/instruct", routing::post(instruct))
         .route("/embed", routing::post(embed))
         .with_state(state);
@@ -65,22 +66,42 @@ async fn initialize() -> Result<AppState> {
     Ok(state)
 }
 
+#[derive(Serialize)]
+struct ContextResponse {
+    ctx_size: usize,
+}
+
+// Shared by the prompt and the completion, so that clients can tell how long their prompts may be
+async fn context(State(state): State<AppState>) -> Json<ContextResponse> {
+    Json(ContextResponse {
+        ctx_size: state.llama.ctx_size,
+    })
+}
+
 #[derive(Deserialize)]
 struct InstructRequest {
     instruction: String,
+    seed: Option<u32>,
 }
 
+// The model and the temperature are returned along with the completion, so that clients can tell what produced it
 #[derive(Serialize)]
 struct InstructResponse {
     completion: String,
+    model: String,
+    temperature: f32,
 }
 
 async fn instruct(
     State(state): State<AppState>,
     Json(payload): Json<InstructRequest>,
 ) -> Result<Json<InstructResponse>, AppError> {
-    let completion = state.llama.inference(&payload.instruction).await?;
-    let response = InstructResponse { completion };
+    let completion = state.llama.inference(&payload.instruction, payload.seed).await?;
+    let response = InstructResponse {
+        completion,
+        model: state.llama.model_name.clone(),
+        temperature: state.llama.temperature,
+    };
     Ok(Json(response))
 }
 
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+CREATE TABLE checkpoints (
+    "name" text PRIMARY KEY,
+    "value" integer NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW()
+)
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+ALTER TABLE items
+ADD COLUMN refreshed_at timestamptz;
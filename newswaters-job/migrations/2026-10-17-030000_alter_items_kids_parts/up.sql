@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Ordered as returned by the API, i.e. in ranked display order
+ALTER TABLE items
+ADD COLUMN kids integer[],
+ADD COLUMN parts integer[];
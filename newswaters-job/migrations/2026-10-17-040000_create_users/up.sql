@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- See: https://github.com/HackerNews/API#users
+CREATE TABLE users (
+    id text PRIMARY KEY,
+    created bigint,
+    karma integer,
+    about text,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW()
+)
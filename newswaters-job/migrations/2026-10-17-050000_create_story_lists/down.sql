@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE story_lists;
+DROP TYPE story_list_type;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- See: https://github.com/HackerNews/API#new-top-and-best-stories
+CREATE TYPE story_list_type AS ENUM('top', 'new', 'best', 'ask', 'show', 'job');
+CREATE TABLE story_lists (
+    id serial PRIMARY KEY,
+    "type" story_list_type NOT NULL,
+    -- Ranked from the first position, i.e. the rank of an item is its (1-based) array index
+    item_ids integer[] NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+CREATE INDEX story_lists_type_created_at_idx ON story_lists ("type", created_at);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+DROP COLUMN fetch_strategy;
+DROP TYPE fetch_strategy;
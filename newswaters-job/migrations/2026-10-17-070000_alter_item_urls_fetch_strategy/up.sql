@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+CREATE TYPE fetch_strategy AS ENUM('http', 'browser');
+ALTER TABLE item_urls
+ADD COLUMN fetch_strategy fetch_strategy;
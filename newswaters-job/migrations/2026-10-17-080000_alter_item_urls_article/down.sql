@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+DROP COLUMN canonical_url,
+DROP COLUMN published_at,
+DROP COLUMN author,
+DROP COLUMN title;
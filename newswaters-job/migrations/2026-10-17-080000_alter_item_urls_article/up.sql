@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+ALTER TABLE item_urls
+ADD COLUMN title text,
+ADD COLUMN author text,
+ADD COLUMN published_at timestamptz,
+ADD COLUMN canonical_url text;
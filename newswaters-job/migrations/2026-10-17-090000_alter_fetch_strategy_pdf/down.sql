@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+-- Values can't be removed from an enum, so the type is recreated without it
+UPDATE item_urls SET fetch_strategy = NULL WHERE fetch_strategy = 'pdf';
+ALTER TYPE fetch_strategy RENAME TO fetch_strategy_old;
+CREATE TYPE fetch_strategy AS ENUM('http', 'browser');
+ALTER TABLE item_urls
+ALTER COLUMN fetch_strategy TYPE fetch_strategy USING fetch_strategy::text::fetch_strategy;
+DROP TYPE fetch_strategy_old;
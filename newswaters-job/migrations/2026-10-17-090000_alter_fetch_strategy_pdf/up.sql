@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TYPE fetch_strategy ADD VALUE 'pdf';
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+ADD COLUMN status_code integer;
+UPDATE item_urls
+SET status_code = CASE status
+    WHEN 'finished' THEN 0
+    WHEN 'unsupported_content' THEN 1
+    ELSE 2
+END;
+ALTER TABLE item_urls
+DROP COLUMN next_attempt_at,
+DROP COLUMN attempts_num,
+DROP COLUMN status;
+DROP TYPE item_url_status;
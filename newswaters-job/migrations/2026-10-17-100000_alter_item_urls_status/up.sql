@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+CREATE TYPE item_url_status AS ENUM(
+    'finished',
+    'unsupported_content',
+    'extraction_failure',
+    'http_error',
+    'timeout',
+    'dns_failure',
+    'connection_failure',
+    'unknown_failure'
+);
+ALTER TABLE item_urls
+ADD COLUMN status item_url_status,
+ADD COLUMN attempts_num integer NOT NULL DEFAULT 1,
+-- Only set for transient failures, which are retried once it has passed
+ADD COLUMN next_attempt_at timestamptz;
+-- 0: Finished, 1: Skipped (PDF), 2: Canceled
+UPDATE item_urls
+SET status = CASE status_code
+    WHEN 0 THEN 'finished'::item_url_status
+    WHEN 1 THEN 'unsupported_content'::item_url_status
+    ELSE 'unknown_failure'::item_url_status
+END,
+next_attempt_at = CASE WHEN status_code = 2 THEN NOW() ELSE NULL END;
+ALTER TABLE item_urls
+DROP COLUMN status_code;
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+-- Values can't be removed from an enum, so the type is recreated without it
+UPDATE item_urls SET status = 'unknown_failure' WHERE status = 'disallowed';
+ALTER TYPE item_url_status RENAME TO item_url_status_old;
+CREATE TYPE item_url_status AS ENUM(
+    'finished',
+    'unsupported_content',
+    'extraction_failure',
+    'http_error',
+    'timeout',
+    'dns_failure',
+    'connection_failure',
+    'unknown_failure'
+);
+ALTER TABLE item_urls
+ALTER COLUMN status TYPE item_url_status USING status::text::item_url_status;
+DROP TYPE item_url_status_old;
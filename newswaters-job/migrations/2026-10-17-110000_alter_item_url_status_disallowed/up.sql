@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TYPE item_url_status ADD VALUE 'disallowed';
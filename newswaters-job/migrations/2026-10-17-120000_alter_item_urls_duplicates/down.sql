@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX item_urls_duplicate_of_idx;
+DROP INDEX item_urls_content_hash_idx;
+DROP INDEX item_urls_normalized_url_idx;
+ALTER TABLE item_urls
+DROP COLUMN duplicate_of,
+DROP COLUMN content_hash,
+DROP COLUMN normalized_url;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+ALTER TABLE item_urls
+ADD COLUMN normalized_url text,
+ADD COLUMN content_hash text,
+-- The item whose text, summary and analysis are reused, which is never a duplicate itself
+ADD COLUMN duplicate_of integer;
+CREATE INDEX item_urls_normalized_url_idx ON item_urls (normalized_url);
+CREATE INDEX item_urls_content_hash_idx ON item_urls (content_hash);
+CREATE INDEX item_urls_duplicate_of_idx ON item_urls (duplicate_of);
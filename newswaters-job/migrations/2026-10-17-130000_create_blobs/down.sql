@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+ADD COLUMN html text;
+-- Compressed contents can't be decompressed in SQL, so only the uncompressed ones are restored
+UPDATE item_urls
+SET html = convert_from(blobs.content, 'UTF8')
+FROM blobs
+WHERE item_urls.html_hash = blobs.hash AND NOT blobs.compressed;
+ALTER TABLE item_urls
+DROP COLUMN html_hash;
+DROP TABLE blobs;
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+
+-- Raw contents, e.g. the HTML of pages, keyed by the SHA-256 hash of their uncompressed bytes
+CREATE TABLE blobs (
+    hash text PRIMARY KEY,
+    -- Compressed by zstd, except for the contents moved here by this migration until `compress-blobs` runs
+    content bytea NOT NULL,
+    compressed boolean NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+ALTER TABLE item_urls
+ADD COLUMN html_hash text;
+INSERT INTO blobs (hash, content, compressed)
+SELECT encode(sha256(convert_to(html, 'UTF8')), 'hex'), convert_to(html, 'UTF8'), false
+FROM item_urls
+WHERE html IS NOT NULL
+ON CONFLICT (hash) DO NOTHING;
+UPDATE item_urls
+SET html_hash = encode(sha256(convert_to(html, 'UTF8')), 'hex')
+WHERE html IS NOT NULL;
+ALTER TABLE item_urls
+DROP COLUMN html;
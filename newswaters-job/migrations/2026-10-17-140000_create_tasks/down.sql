@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE tasks;
+DROP TYPE task_status;
+DROP TYPE task_kind;
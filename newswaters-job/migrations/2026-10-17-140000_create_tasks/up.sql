@@ -0,0 +1,32 @@
+-- Your SQL goes here
+
+CREATE TYPE task_kind AS ENUM(
+    'fetch_item_url',
+    'summarize_text',
+    'analyze_story_text',
+    'analyze_comment_text',
+    'analyze_summary',
+    'embed_summary',
+    'embed_keyword'
+);
+-- Finished tasks are deleted, so only the dead ones are kept for inspection
+CREATE TYPE task_status AS ENUM(
+    'queued',
+    'running',
+    'dead'
+);
+CREATE TABLE tasks (
+    id serial PRIMARY KEY,
+    kind task_kind NOT NULL,
+    item_id integer NOT NULL,
+    status task_status NOT NULL DEFAULT 'queued',
+    attempts_num integer NOT NULL DEFAULT 0,
+    run_at timestamptz NOT NULL,
+    worker text,
+    heartbeat_at timestamptz,
+    last_error text,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    UNIQUE (kind, item_id)
+);
+CREATE INDEX tasks_kind_status_run_at_idx ON tasks (kind, status, run_at);
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+
+-- Values can't be removed from an enum, so the type is recreated without it
+DELETE FROM tasks WHERE kind = 'collect_item';
+ALTER TYPE task_kind RENAME TO task_kind_old;
+CREATE TYPE task_kind AS ENUM(
+    'fetch_item_url',
+    'summarize_text',
+    'analyze_story_text',
+    'analyze_comment_text',
+    'analyze_summary',
+    'embed_summary',
+    'embed_keyword'
+);
+ALTER TABLE tasks
+ALTER COLUMN kind TYPE task_kind USING kind::text::task_kind;
+DROP TYPE task_kind_old;
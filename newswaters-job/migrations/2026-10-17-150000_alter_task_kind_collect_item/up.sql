@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TYPE task_kind ADD VALUE 'collect_item' BEFORE 'fetch_item_url';
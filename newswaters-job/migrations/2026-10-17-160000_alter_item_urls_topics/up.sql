@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Summaries generated before are left as they were, i.e. with their topics inlined
+ALTER TABLE item_urls
+ADD COLUMN topics text[];
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+DROP COLUMN summary_prompts;
+ALTER TABLE analyses
+DROP COLUMN keyword_prompts,
+DROP COLUMN text_passage_prompts,
+DROP COLUMN summary_passage_prompts;
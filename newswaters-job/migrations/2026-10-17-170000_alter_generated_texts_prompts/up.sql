@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- The keys of the prompt templates a text was generated by, e.g. '{summary@v1,merged_summary@v1}',
+-- which are NULL for the texts generated before the templates were versioned
+ALTER TABLE item_urls
+ADD COLUMN summary_prompts text[];
+ALTER TABLE analyses
+ADD COLUMN keyword_prompts text[],
+ADD COLUMN text_passage_prompts text[],
+ADD COLUMN summary_passage_prompts text[];
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+-- Every call to the inference server, including the failed ones
+CREATE TABLE generations (
+    id serial PRIMARY KEY,
+    item_id integer NOT NULL REFERENCES items,
+    -- The key of the prompt template, e.g. 'summary@v1'
+    prompt text NOT NULL,
+    -- The SHA-256 of the rendered prompt
+    input_hash text NOT NULL,
+    model text,
+    temperature real,
+    output text,
+    error text,
+    duration_ms integer NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+CREATE INDEX generations_item_id_idx ON generations (item_id);
+CREATE INDEX generations_prompt_idx ON generations (prompt);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE item_urls
+DROP COLUMN summary_rejection;
+ALTER TABLE generations
+DROP COLUMN seed;
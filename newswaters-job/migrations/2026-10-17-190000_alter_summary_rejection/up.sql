@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Why the summary has been rejected, in which case the summary is left NULL and not generated again
+ALTER TABLE item_urls
+ADD COLUMN summary_rejection text;
+ALTER TABLE generations
+ADD COLUMN seed bigint;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Refreshing items looks up the ones posted within a window
+CREATE INDEX items_time_idx ON items (time);
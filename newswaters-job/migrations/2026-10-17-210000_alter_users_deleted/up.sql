@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Accounts which have been deleted are recorded without their profile, so that they are not collected first again
+ALTER TABLE users
+ADD COLUMN deleted boolean NOT NULL DEFAULT false;
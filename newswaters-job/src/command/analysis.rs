@@ -1,213 +1,438 @@
-use std::env;
-
 use anyhow::Result;
+use clap::{builder::FalseyValueParser, Args};
 use serde::Serialize;
 
+use std::{collections::HashMap, path::PathBuf};
+
 use crate::{
-    command,
+    command::{
+        self,
+        task::{QueueArgs, Worker},
+    },
     repository::Repository,
-    service::{hacker_news, inference, search_engine, Analysis},
+    service::{hacker_news, inference, prompt, search_engine, Analysis, TaskKind},
 };
 
-pub(crate) async fn analyze_story_texts(mut repo: Repository) -> Result<()> {
-    let texts_num: usize = env::var("JOB_ANALYZE_STORY_TEXTS_NUM")
-        .unwrap_or("30".to_string())
-        .parse()?;
-    let top_story_ids = hacker_news::get_top_story_ids().await?;
-    let mut analyses = repo.find_keyword_missing_analyses(&top_story_ids)?;
-    // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
-    //   as `LIMIT` doesn't maintain the order of top stories' ids.
-    analyses.truncate(texts_num);
-    if env::var("JOB_ANALYZE_ADDITIONAL_TEXTS").is_ok() && analyses.len() < texts_num {
-        let mut additional_items =
-            repo.find_keyword_missing_analyses_excluding(&top_story_ids, texts_num - analyses.len())?;
-        analyses.append(&mut additional_items);
+#[derive(Args)]
+pub(crate) struct AnalyzeStoryTextsArgs {
+    /// The number of top stories to analyze
+    #[arg(long, env = "JOB_ANALYZE_STORY_TEXTS_NUM", default_value_t = 30)]
+    texts_num: usize,
+    /// Fill up the rest with the latest stories, when there are not enough top stories to analyze
+    #[arg(long, env = "JOB_ANALYZE_ADDITIONAL_TEXTS", value_parser = FalseyValueParser::new())]
+    additional_texts: bool,
+    /// Also analyze again the texts whose keywords were generated by older versions of the prompts
+    #[arg(long, env = "JOB_ANALYZE_OUTDATED_TEXTS", value_parser = FalseyValueParser::new())]
+    outdated: bool,
+    /// Only analyze the texts of pages once they've been summarized
+    #[arg(long, env = "JOB_FIND_ANALYSES_FOLLOW_SUMMARIES", value_parser = FalseyValueParser::new())]
+    follow_summaries: bool,
+    /// Shorter lines of the texts of pages are left out
+    #[arg(long, env = "JOB_TEXT_MIN_LINE_LEN", default_value_t = 80)]
+    text_min_line_len: usize,
+    /// The texts of pages are cut down to this number of bytes
+    #[arg(long, env = "JOB_TEXT_MAX_TOTAL_LEN", default_value_t = 4800)]
+    text_max_total_len: usize,
+    /// The directory of the prompts, "<id>/v<version>.txt"
+    #[arg(long, env = "JOB_PROMPTS_DIR", default_value = "prompts")]
+    prompts_dir: PathBuf,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn analyze_story_texts(repo: Repository, args: AnalyzeStoryTextsArgs) -> Result<()> {
+    let texts_num = args.texts_num;
+    let worker = Worker::start(&repo, TaskKind::AnalyzeStoryText, &args.queue);
+    let prompts = prompt::find_keys(&args.prompts_dir, &inference::KEYWORD_PROMPTS)?;
+    if !args.queue.no_enqueue {
+        let top_story_ids = hacker_news::get_top_story_ids().await?;
+        let ids = top_story_ids.clone();
+        let outdated_prompts = args.outdated.then(|| prompts.clone());
+        let mut analyses = repo
+            .run(move |repo| repo.find_keyword_missing_analyses(&ids, outdated_prompts.as_deref()))
+            .await?;
+        // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
+        //   as `LIMIT` doesn't maintain the order of top stories' ids.
+        analyses.truncate(texts_num);
+        if args.additional_texts && analyses.len() < texts_num {
+            let limit = texts_num - analyses.len();
+            let outdated_prompts = args.outdated.then(|| prompts.clone());
+            let follow_summaries = args.follow_summaries;
+            let mut additional_items = repo
+                .run(move |repo| {
+                    repo.find_keyword_missing_analyses_excluding(
+                        &top_story_ids,
+                        limit,
+                        outdated_prompts.as_deref(),
+                        follow_summaries,
+                    )
+                })
+                .await?;
+            analyses.append(&mut additional_items);
+        }
+        let ids = analyses.iter().map(|(id, _, _, _)| *id).collect::<Vec<i32>>();
+        worker.enqueue(&repo, ids).await?;
     }
-    for (id, title, text, url_text) in analyses {
-        let text = if let Some(text) = text {
-            text
-        } else if let Some(url_text) = url_text {
-            command::shorten_text(&url_text)?
-        } else {
-            continue;
-        };
-        let start_time = std::time::Instant::now();
-        let keyword = match inference::instruct_keyword(&title, &text).await {
-            Ok(keyword) => keyword,
-            Err(e) => {
-                println!("[ERR] inference.instruct_keyword (id={id}): err={e}");
-                continue;
-            }
-        };
-        println!(
-            "[INFO] main.analyze_story_texts (id={}): text.len={}, keyword.len={}, elapsed_time={:?}",
-            id,
-            text.len(),
-            keyword.len(),
-            start_time.elapsed()
-        );
-        repo.insert_analysis(Analysis {
-            item_id: id,
-            keyword: Some(keyword),
-            text_passage: None,
-            summary_passage: None,
-        })?;
+    loop {
+        let tasks = worker.claim(&repo, 1).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        for task in tasks {
+            let result = analyze_story_text(&repo, task.item_id, &args, &prompts).await;
+            worker.finish(&repo, &task, result).await?;
+        }
     }
     Ok(())
 }
 
-pub(crate) async fn analyze_comment_texts(mut repo: Repository) -> Result<()> {
-    let min_len: usize = env::var("JOB_ANALYZE_COMMENT_TEXT_MIN_LEN")
-        .unwrap_or("120".to_string())
-        .parse()?;
-    let max_len: usize = env::var("JOB_ANALYZE_COMMENT_TEXT_MAX_LEN")
-        .unwrap_or("4800".to_string())
-        .parse()?;
-    let texts_num: usize = env::var("JOB_ANALYZE_COMMENT_TEXTS_NUM")
-        .unwrap_or("30".to_string())
-        .parse()?;
-    let analyses = repo.find_text_passage_missing_analyses(min_len, texts_num)?;
-    for (id, mut text) in analyses {
-        text.truncate(max_len);
-        let start_time = std::time::Instant::now();
-        let anchor_passage = match inference::instruct_comment_anchor_passage(&text).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_comment_anchor_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        let entailment_passage = match inference::instruct_entailment_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_entailment_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        let contradiction_passage = match inference::instruct_contradiction_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_contradiction_passage (id={id}): err={e}");
-                continue;
-            }
-        };
+async fn analyze_story_text(
+    repo: &Repository,
+    id: i32,
+    args: &AnalyzeStoryTextsArgs,
+    prompts: &[String],
+) -> Result<()> {
+    let current_prompts = prompts.to_vec();
+    let (_, title, text, url_text) = match repo
+        .run(move |repo| repo.find_keyword_missing_analyses(&[id], Some(&current_prompts)))
+        .await?
+        .pop()
+    {
+        Some(analysis) => analysis,
+        None => return Ok(()),
+    };
+    let text = if let Some(text) = text {
+        text
+    } else if let Some(url_text) = url_text {
+        command::shorten_text(&url_text, args.text_min_line_len, args.text_max_total_len)
+    } else {
+        return Ok(());
+    };
+    let start_time = std::time::Instant::now();
+    let mut generations = vec![];
+    let keyword = inference::instruct_keyword(&args.prompts_dir, &title, &text, &mut generations).await;
+    repo.run(move |repo| repo.insert_generations(id, generations)).await?;
+    let keyword = keyword?;
+    println!(
+        "[INFO] main.analyze_story_texts (id={}): text.len={}, keyword.len={}, elapsed_time={:?}",
+        id,
+        text.len(),
+        keyword.len(),
+        start_time.elapsed()
+    );
+    let analysis = Analysis {
+        item_id: id,
+        keyword: Some(keyword),
+        text_passage: None,
+        summary_passage: None,
+        prompts: prompts.to_vec(),
+    };
+    repo.run(move |repo| repo.insert_analysis(analysis)).await?;
+    Ok(())
+}
+
+#[derive(Args)]
+pub(crate) struct AnalyzeCommentTextsArgs {
+    /// Shorter comments are not worth analyzing
+    #[arg(long, env = "JOB_ANALYZE_COMMENT_TEXT_MIN_LEN", default_value_t = 120)]
+    min_len: usize,
+    /// Longer comments are truncated
+    #[arg(long, env = "JOB_ANALYZE_COMMENT_TEXT_MAX_LEN", default_value_t = 4800)]
+    max_len: usize,
+    /// The number of the latest comments to analyze
+    #[arg(long, env = "JOB_ANALYZE_COMMENT_TEXTS_NUM", default_value_t = 30)]
+    texts_num: usize,
+    /// Also analyze again the comments whose passages were generated by older versions of the prompts
+    #[arg(long, env = "JOB_ANALYZE_OUTDATED_COMMENT_TEXTS", value_parser = FalseyValueParser::new())]
+    outdated: bool,
+    /// The number of words asked for in the passage of a comment
+    #[arg(
+        long,
+        env = "JOB_INSTRUCT_COMMENT_ANCHOR_PASSAGE_MAX_WORDS_COUNT",
+        default_value_t = 40
+    )]
+    anchor_passage_max_words_count: usize,
+    /// The rate of the words of a passage given to generate a random one
+    #[arg(
+        long,
+        env = "JOB_INSTRUCT_RANDOM_PASSAGE_WORDS_RETENTION_RATE",
+        default_value_t = 0.1
+    )]
+    random_passage_words_retention_rate: f32,
+    /// The directory of the prompts, "<id>/v<version>.txt"
+    #[arg(long, env = "JOB_PROMPTS_DIR", default_value = "prompts")]
+    prompts_dir: PathBuf,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn analyze_comment_texts(repo: Repository, args: AnalyzeCommentTextsArgs) -> Result<()> {
+    let worker = Worker::start(&repo, TaskKind::AnalyzeCommentText, &args.queue);
+    let prompts = prompt::find_keys(&args.prompts_dir, &inference::TEXT_PASSAGE_PROMPTS)?;
+    if !args.queue.no_enqueue {
+        let (min_len, texts_num) = (args.min_len, args.texts_num);
+        let outdated_prompts = args.outdated.then(|| prompts.clone());
+        let analyses = repo
+            .run(move |repo| repo.find_text_passage_missing_analyses(min_len, texts_num, outdated_prompts.as_deref()))
+            .await?;
+        let ids = analyses.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+        worker.enqueue(&repo, ids).await?;
+    }
+    loop {
+        let tasks = worker.claim(&repo, 1).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        for task in tasks {
+            let result = analyze_comment_text(&repo, task.item_id, &args, &prompts).await;
+            worker.finish(&repo, &task, result).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn analyze_comment_text(
+    repo: &Repository,
+    id: i32,
+    args: &AnalyzeCommentTextsArgs,
+    prompts: &[String],
+) -> Result<()> {
+    let current_prompts = prompts.to_vec();
+    let (_, mut text) = match repo
+        .run(move |repo| repo.find_text_passage_missing_comments(&[id], Some(&current_prompts)))
+        .await?
+        .pop()
+    {
+        Some(comment) => comment,
+        None => return Ok(()),
+    };
+    text.truncate(args.max_len);
+    let start_time = std::time::Instant::now();
+    let mut generations = vec![];
+    let passages = async {
+        let prompts_dir = args.prompts_dir.as_path();
+        let anchor_passage = inference::instruct_comment_anchor_passage(
+            prompts_dir,
+            &text,
+            args.anchor_passage_max_words_count,
+            &mut generations,
+        )
+        .await?;
+        let entailment_passage =
+            inference::instruct_entailment_passage(prompts_dir, &anchor_passage, &mut generations).await?;
+        let contradiction_passage =
+            inference::instruct_contradiction_passage(prompts_dir, &anchor_passage, &mut generations).await?;
         // TODO: Generate a genuinely irrelevant passage
-        let irrelevance_passage = match inference::instruct_random_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_random_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        println!(
-            "[INFO] main.analyze_comment_texts (id={}): text.len={}, \
-                anchor_passage.len={}, entailment_passage.len={}, contradiction_passage.len={}, irrelevance_passage.len={}, \
-                elapsed_time={:?}",
-            id,
-            text.len(),
-            anchor_passage.len(),
-            entailment_passage.len(),
-            contradiction_passage.len(),
-            irrelevance_passage.len(),
-            start_time.elapsed()
-        );
-        let text_passage = serde_json::to_string(&Passage {
-            anchor: vec![anchor_passage],
-            entailment: vec![entailment_passage],
-            contradiction: vec![contradiction_passage],
-            irrelevance: vec![irrelevance_passage],
-            subject: vec![],
-        })?;
-        repo.insert_analysis(Analysis {
-            item_id: id,
-            keyword: None,
-            text_passage: Some(text_passage),
-            summary_passage: None,
-        })?;
+        let irrelevance_passage = inference::instruct_random_passage(
+            prompts_dir,
+            &anchor_passage,
+            args.random_passage_words_retention_rate,
+            &mut generations,
+        )
+        .await?;
+        anyhow::Ok((
+            anchor_passage,
+            entailment_passage,
+            contradiction_passage,
+            irrelevance_passage,
+        ))
     }
+    .await;
+    repo.run(move |repo| repo.insert_generations(id, generations)).await?;
+    let (anchor_passage, entailment_passage, contradiction_passage, irrelevance_passage) = passages?;
+    println!(
+        "[INFO] main.analyze_comment_texts (id={}): text.len={}, \
+            anchor_passage.len={}, entailment_passage.len={}, contradiction_passage.len={}, irrelevance_passage.len={}, \
+            elapsed_time={:?}",
+        id,
+        text.len(),
+        anchor_passage.len(),
+        entailment_passage.len(),
+        contradiction_passage.len(),
+        irrelevance_passage.len(),
+        start_time.elapsed()
+    );
+    let text_passage = serde_json::to_string(&Passage {
+        anchor: vec![anchor_passage],
+        entailment: vec![entailment_passage],
+        contradiction: vec![contradiction_passage],
+        irrelevance: vec![irrelevance_passage],
+        subject: vec![],
+    })?;
+    let analysis = Analysis {
+        item_id: id,
+        keyword: None,
+        text_passage: Some(text_passage),
+        summary_passage: None,
+        prompts: prompts.to_vec(),
+    };
+    repo.run(move |repo| repo.insert_analysis(analysis)).await?;
     Ok(())
 }
 
-pub(crate) async fn analyze_summaries(mut repo: Repository) -> Result<()> {
-    let summaries_num: usize = env::var("JOB_ANALYZE_SUMMARIES_NUM")
-        .unwrap_or("30".to_string())
-        .parse()?;
-    let top_story_ids = hacker_news::get_top_story_ids().await?;
-    let mut analyses = repo.find_summary_passage_missing_analyses(&top_story_ids)?;
-    // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
-    //   as `LIMIT` doesn't maintain the order of top stories' ids.
-    analyses.truncate(summaries_num);
-    if env::var("JOB_ANALYZE_ADDITIONAL_SUMMARIES").is_ok() && analyses.len() < summaries_num {
-        let mut additional_items =
-            repo.find_summary_passage_missing_analyses_excluding(&top_story_ids, summaries_num - analyses.len())?;
-        analyses.append(&mut additional_items);
+#[derive(Args)]
+pub(crate) struct AnalyzeSummariesArgs {
+    /// The number of top stories' summaries to analyze
+    #[arg(long, env = "JOB_ANALYZE_SUMMARIES_NUM", default_value_t = 30)]
+    summaries_num: usize,
+    /// Fill up the rest with the latest summaries, when there are not enough top stories to analyze
+    #[arg(long, env = "JOB_ANALYZE_ADDITIONAL_SUMMARIES", value_parser = FalseyValueParser::new())]
+    additional_summaries: bool,
+    /// Also analyze again the summaries whose passages were generated by older versions of the prompts
+    #[arg(long, env = "JOB_ANALYZE_OUTDATED_SUMMARIES", value_parser = FalseyValueParser::new())]
+    outdated: bool,
+    /// The number of words asked for in the passage of a summary
+    #[arg(
+        long,
+        env = "JOB_INSTRUCT_SUMMARY_ANCHOR_PASSAGE_MAX_WORDS_COUNT",
+        default_value_t = 40
+    )]
+    anchor_passage_max_words_count: usize,
+    /// The rate of the words of a passage given to generate a random one
+    #[arg(
+        long,
+        env = "JOB_INSTRUCT_RANDOM_PASSAGE_WORDS_RETENTION_RATE",
+        default_value_t = 0.1
+    )]
+    random_passage_words_retention_rate: f32,
+    /// The number of subjects asked for in a summary
+    #[arg(long, env = "JOB_INSTRUCT_SUBJECT_PASSAGE_MAX_SUBJECTS_NUM", default_value_t = 5)]
+    subject_passage_max_subjects_num: usize,
+    /// The number of words asked for in a subject
+    #[arg(long, env = "JOB_INSTRUCT_SUBJECT_PASSAGE_MAX_WORDS_COUNT", default_value_t = 5)]
+    subject_passage_max_words_count: usize,
+    /// The directory of the prompts, "<id>/v<version>.txt"
+    #[arg(long, env = "JOB_PROMPTS_DIR", default_value = "prompts")]
+    prompts_dir: PathBuf,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn analyze_summaries(repo: Repository, args: AnalyzeSummariesArgs) -> Result<()> {
+    let summaries_num = args.summaries_num;
+    let worker = Worker::start(&repo, TaskKind::AnalyzeSummary, &args.queue);
+    let prompts = prompt::find_keys(&args.prompts_dir, &inference::SUMMARY_PASSAGE_PROMPTS)?;
+    if !args.queue.no_enqueue {
+        let top_story_ids = hacker_news::get_top_story_ids().await?;
+        let ids = top_story_ids.clone();
+        let outdated_prompts = args.outdated.then(|| prompts.clone());
+        let mut analyses = repo
+            .run(move |repo| repo.find_summary_passage_missing_analyses(&ids, outdated_prompts.as_deref()))
+            .await?;
+        // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
+        //   as `LIMIT` doesn't maintain the order of top stories' ids.
+        analyses.truncate(summaries_num);
+        if args.additional_summaries && analyses.len() < summaries_num {
+            let limit = summaries_num - analyses.len();
+            let outdated_prompts = args.outdated.then(|| prompts.clone());
+            let mut additional_items = repo
+                .run(move |repo| {
+                    repo.find_summary_passage_missing_analyses_excluding(
+                        &top_story_ids,
+                        limit,
+                        outdated_prompts.as_deref(),
+                    )
+                })
+                .await?;
+            analyses.append(&mut additional_items);
+        }
+        let ids = analyses.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+        worker.enqueue(&repo, ids).await?;
     }
-    for (id, summary) in analyses {
-        let start_time = std::time::Instant::now();
-        let anchor_passage = match inference::instruct_summary_anchor_passage(&summary).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_summary_anchor_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        let entailment_passage = match inference::instruct_entailment_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_entailment_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        let contradiction_passage = match inference::instruct_contradiction_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_contradiction_passage (id={id}): err={e}");
-                continue;
-            }
-        };
+    loop {
+        let tasks = worker.claim(&repo, 1).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        for task in tasks {
+            let result = analyze_summary(&repo, task.item_id, &args, &prompts).await;
+            worker.finish(&repo, &task, result).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn analyze_summary(repo: &Repository, id: i32, args: &AnalyzeSummariesArgs, prompts: &[String]) -> Result<()> {
+    let current_prompts = prompts.to_vec();
+    let (_, summary) = match repo
+        .run(move |repo| repo.find_summary_passage_missing_analyses(&[id], Some(&current_prompts)))
+        .await?
+        .pop()
+    {
+        Some(analysis) => analysis,
+        None => return Ok(()),
+    };
+    let start_time = std::time::Instant::now();
+    let mut generations = vec![];
+    let passages = async {
+        let prompts_dir = args.prompts_dir.as_path();
+        let anchor_passage = inference::instruct_summary_anchor_passage(
+            prompts_dir,
+            &summary,
+            args.anchor_passage_max_words_count,
+            &mut generations,
+        )
+        .await?;
+        let entailment_passage =
+            inference::instruct_entailment_passage(prompts_dir, &anchor_passage, &mut generations).await?;
+        let contradiction_passage =
+            inference::instruct_contradiction_passage(prompts_dir, &anchor_passage, &mut generations).await?;
         // TODO: Generate a genuinely irrelevant passage
-        let irrelevance_passage = match inference::instruct_random_passage(&anchor_passage).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_random_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        let subject_passage = match inference::instruct_subject_passage(&summary).await {
-            Ok(passage) => passage,
-            Err(e) => {
-                println!("[ERR] inference.instruct_subject_passage (id={id}): err={e}");
-                continue;
-            }
-        };
-        println!(
-            "[INFO] main.analyze_summaries (id={}): summary.len={}, \
-                anchor_passage.len={}, entailment_passage.len={}, contradiction_passage.len={}, irrelevance_passage.len={}, \
-                subject_passage.len={}, \
-                elapsed_time={:?}",
-            id,
-            summary.len(),
-            anchor_passage.len(),
-            entailment_passage.len(),
-            contradiction_passage.len(),
-            irrelevance_passage.len(),
-            subject_passage.len(),
-            start_time.elapsed()
-        );
-        let summary_passage = serde_json::to_string(&Passage {
-            anchor: vec![anchor_passage],
-            entailment: vec![entailment_passage],
-            contradiction: vec![contradiction_passage],
-            irrelevance: vec![irrelevance_passage],
-            subject: subject_passage.split("\n").map(str::to_string).collect(),
-        })?;
-        repo.insert_analysis(Analysis {
-            item_id: id,
-            keyword: None,
-            text_passage: None,
-            summary_passage: Some(summary_passage),
-        })?;
+        let irrelevance_passage = inference::instruct_random_passage(
+            prompts_dir,
+            &anchor_passage,
+            args.random_passage_words_retention_rate,
+            &mut generations,
+        )
+        .await?;
+        let subject_passage = inference::instruct_subject_passage(
+            prompts_dir,
+            &summary,
+            args.subject_passage_max_subjects_num,
+            args.subject_passage_max_words_count,
+            &mut generations,
+        )
+        .await?;
+        anyhow::Ok((
+            anchor_passage,
+            entailment_passage,
+            contradiction_passage,
+            irrelevance_passage,
+            subject_passage,
+        ))
     }
+    .await;
+    repo.run(move |repo| repo.insert_generations(id, generations)).await?;
+    let (anchor_passage, entailment_passage, contradiction_passage, irrelevance_passage, subject_passage) = passages?;
+    println!(
+        "[INFO] main.analyze_summaries (id={}): summary.len={}, \
+            anchor_passage.len={}, entailment_passage.len={}, contradiction_passage.len={}, irrelevance_passage.len={}, \
+            subject_passage.len={}, \
+            elapsed_time={:?}",
+        id,
+        summary.len(),
+        anchor_passage.len(),
+        entailment_passage.len(),
+        contradiction_passage.len(),
+        irrelevance_passage.len(),
+        subject_passage.len(),
+        start_time.elapsed()
+    );
+    let summary_passage = serde_json::to_string(&Passage {
+        anchor: vec![anchor_passage],
+        entailment: vec![entailment_passage],
+        contradiction: vec![contradiction_passage],
+        irrelevance: vec![irrelevance_passage],
+        subject: subject_passage.split("\n").map(str::to_string).collect(),
+    })?;
+    let analysis = Analysis {
+        item_id: id,
+        keyword: None,
+        text_passage: None,
+        summary_passage: Some(summary_passage),
+        prompts: prompts.to_vec(),
+    };
+    repo.run(move |repo| repo.insert_analysis(analysis)).await?;
     Ok(())
 }
 
@@ -220,21 +445,55 @@ struct Passage {
     subject: Vec<String>,
 }
 
-pub(crate) async fn embed_keywords(mut repo: Repository) -> Result<()> {
-    let collection_name = env::var("SEARCH_ENGINE_VECTOR_KEYWORD_COLLECTION_NAME")?;
-    let keywords_num: usize = env::var("JOB_EMBED_KEYWORDS_NUM")
-        .unwrap_or("1000000".to_string())
-        .parse()?;
-    let chunk_size: usize = env::var("JOB_CHUNK_SIZE").unwrap_or("50".to_string()).parse()?;
-    let keyword_existing_ids = repo.find_keyword_existing_analyses(keywords_num)?;
-    let embedding_missing_ids = search_engine::find_missing(collection_name.clone(), keyword_existing_ids).await?;
-    for chunk in embedding_missing_ids.chunks(chunk_size) {
-        let analysis_keywords = repo.find_analysis_keywords(chunk)?;
-        for (id, keyword) in analysis_keywords {
-            let embedding = inference::embed(keyword).await?;
-            search_engine::upsert(collection_name.clone(), id, embedding).await?;
-            println!("[INFO] main.embed_keywords (id={})", id);
+#[derive(Args)]
+pub(crate) struct EmbedKeywordsArgs {
+    #[arg(long, env = "SEARCH_ENGINE_VECTOR_KEYWORD_COLLECTION_NAME")]
+    collection_name: String,
+    /// The number of the latest keywords to embed if missing
+    #[arg(long, env = "JOB_EMBED_KEYWORDS_NUM", default_value_t = 1000000)]
+    keywords_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 50)]
+    chunk_size: usize,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn embed_keywords(repo: Repository, args: EmbedKeywordsArgs) -> Result<()> {
+    let collection_name = args.collection_name;
+    let worker = Worker::start(&repo, TaskKind::EmbedKeyword, &args.queue);
+    if !args.queue.no_enqueue {
+        let keywords_num = args.keywords_num;
+        let keyword_existing_ids = repo
+            .run(move |repo| repo.find_keyword_existing_analyses(keywords_num))
+            .await?;
+        let embedding_missing_ids = search_engine::find_missing(collection_name.clone(), keyword_existing_ids).await?;
+        worker.enqueue(&repo, embedding_missing_ids).await?;
+    }
+    loop {
+        let tasks = worker.claim(&repo, args.chunk_size).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        let ids = tasks.iter().map(|t| t.item_id).collect::<Vec<i32>>();
+        let mut analysis_keywords = repo
+            .run(move |repo| repo.find_analysis_keywords(&ids))
+            .await?
+            .into_iter()
+            .collect::<HashMap<i32, String>>();
+        for task in tasks {
+            let result = match analysis_keywords.remove(&task.item_id) {
+                Some(keyword) => embed_keyword(&collection_name, task.item_id, keyword).await,
+                None => Ok(()),
+            };
+            worker.finish(&repo, &task, result).await?;
         }
     }
     Ok(())
 }
+
+async fn embed_keyword(collection_name: &str, id: i32, keyword: String) -> Result<()> {
+    let embedding = inference::embed(keyword).await?;
+    search_engine::upsert(collection_name.to_string(), id, embedding).await?;
+    println!("[INFO] main.embed_keywords (id={})", id);
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use clap::Args;
+
+use crate::repository::Repository;
+
+#[derive(Args)]
+pub(crate) struct CompressBlobsArgs {
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 100)]
+    chunk_size: usize,
+}
+
+// Compresses the blobs moved from other tables by migrations, or moves all the blobs of the database to the filesystem
+// if blobs are written there
+pub(crate) async fn compress_blobs(repo: Repository, args: CompressBlobsArgs) -> Result<()> {
+    let chunk_size = args.chunk_size;
+    loop {
+        let (hashes_len, compressed_num) = repo.run(move |repo| compress_chunk_blobs(repo, chunk_size)).await?;
+        if hashes_len == 0 {
+            break;
+        }
+        println!(
+            "[INFO] main.compress_blobs: hashes.len={}, compressed_num={}",
+            hashes_len, compressed_num
+        );
+        // Blobs failing every time would be found again and again
+        if compressed_num == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Reading, compressing and writing blobs are all blocking, so a whole chunk is done on a blocking thread
+fn compress_chunk_blobs(repo: &Repository, chunk_size: usize) -> Result<(usize, usize)> {
+    let hashes = repo.find_pending_blobs(chunk_size)?;
+    let mut compressed_num = 0;
+    for hash in hashes.iter() {
+        let content = match repo.find_blob(hash) {
+            Ok(Some(content)) => content,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("[ERR] repo.find_blob (hash={hash}): err={e}");
+                continue;
+            }
+        };
+        repo.insert_blob(&content)?;
+        compressed_num += 1;
+    }
+    Ok((hashes.len(), compressed_num))
+}
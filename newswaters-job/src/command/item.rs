@@ -1,162 +1,885 @@
-use std::{collections::HashMap, env, ops::DerefMut, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{bail, Result};
-use tokio::{
-    self,
-    sync::{Mutex, Semaphore},
+use chrono::{DateTime, Local};
+use clap::{builder::FalseyValueParser, Args};
+use tokio::{self, sync::Semaphore, task::JoinHandle};
+
+use crate::service::{
+    browser::BrowserPool,
+    canonicalizer,
+    hacker_news::{self, FetchOptions},
+    inference,
+    politeness::Politeness,
+    prompt, search_engine,
+    validator::SummaryChecks,
+    Generation, ItemUrl, ItemUrlFailure, Summary, SummaryRejection, TaskKind,
+};
+use crate::{
+    command::{
+        self,
+        task::{QueueArgs, Worker},
+    },
+    repository::Repository,
 };
 
-use crate::service::{self, hacker_news, inference, search_engine};
-use crate::{command, repository::Repository};
+const FOLLOW_ITEMS_CHECKPOINT: &str = "follow_items";
 
-pub(crate) async fn collect_items(repo: Arc<Mutex<Repository>>) -> Result<()> {
-    let items_num: i32 = env::var("JOB_COLLECT_ITEMS_NUM")
-        .unwrap_or("1000000".to_string())
-        .parse()?;
-    let permits_num: usize = env::var("JOB_PERMITS_NUM").unwrap_or("100".to_string()).parse()?;
-    let chunk_size: i32 = env::var("JOB_CHUNK_SIZE").unwrap_or("1000".to_string()).parse()?;
-    let max_id = hacker_news::get_max_item_id().await?;
-    let min_id = std::cmp::max(0, max_id - (items_num - 1));
+#[derive(Args)]
+pub(crate) struct IdRangeArgs {
+    /// The smallest item id to process
+    #[arg(long)]
+    min_id: Option<i32>,
+    /// The largest item id to process
+    #[arg(long)]
+    max_id: Option<i32>,
+}
+
+#[derive(Args)]
+pub(crate) struct FetchArgs {
+    /// The number of browsers rendering pages at the same time
+    #[arg(long, env = "JOB_BROWSERS_NUM", default_value_t = 4)]
+    browsers_num: usize,
+    /// The number of pages a browser renders before it's relaunched
+    #[arg(long, env = "JOB_BROWSER_MAX_PAGES_NUM", default_value_t = 50)]
+    browser_max_pages_num: usize,
+    /// The number of concurrent requests to a domain
+    #[arg(long, env = "JOB_DOMAIN_PERMITS_NUM", default_value_t = 2)]
+    domain_permits_num: usize,
+    /// The number of requests to a domain per second
+    #[arg(long, env = "JOB_DOMAIN_REQUESTS_PER_SEC", default_value_t = 1.0)]
+    domain_requests_per_sec: f64,
+    /// Sent to websites and matched against their robots.txt [default: newswaters/<version>]
+    #[arg(long, env = "JOB_USER_AGENT")]
+    user_agent: Option<String>,
+    /// The delay before retrying a failed URL, which doubles after each attempt
+    #[arg(long, env = "JOB_RETRY_ITEM_URLS_BASE_SECS", default_value_t = 600)]
+    retry_base_secs: i64,
+    /// URLs are given up after this number of attempts
+    #[arg(long, env = "JOB_RETRY_ITEM_URLS_MAX_ATTEMPTS_NUM", default_value_t = 5)]
+    max_attempts_num: i32,
+    /// PDFs larger than this number of bytes are skipped
+    #[arg(long, env = "JOB_PDF_MAX_SIZE", default_value_t = 20000000)]
+    pdf_max_size: usize,
+    /// Pages larger than this number of bytes are skipped
+    #[arg(long, env = "JOB_HTML_MAX_SIZE", default_value_t = 10000000)]
+    html_max_size: usize,
+    /// Domains whose pages are always rendered by a browser, e.g. "twitter.com,medium.com"
+    #[arg(long, env = "JOB_FETCH_BROWSER_DOMAINS", value_delimiter = ',')]
+    browser_domains: Vec<String>,
+    /// Static pages with fewer words are rendered by a browser
+    #[arg(long, env = "JOB_FETCH_MIN_WORDS_COUNT", default_value_t = 150)]
+    min_words_count: usize,
+    /// Texts of fewer bytes are not matched against other pages by their content, as they're mostly bot walls,
+    /// consent pages and error pages
+    #[arg(long, env = "JOB_FETCH_MIN_DUPLICATE_TEXT_LEN", default_value_t = 1000)]
+    min_duplicate_text_len: usize,
+}
+
+#[derive(Args)]
+pub(crate) struct CollectItemsArgs {
+    /// The number of items to collect, counting down from the max item id
+    #[arg(long, env = "JOB_COLLECT_ITEMS_NUM", default_value_t = 1000000)]
+    items_num: i32,
+    #[command(flatten)]
+    id_range: IdRangeArgs,
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 100)]
+    permits_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: i32,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+// Replicas walk through the same chunks, and split the items of each chunk through the task queue
+pub(crate) async fn collect_items(repo: Repository, args: CollectItemsArgs) -> Result<()> {
+    let max_id = match args.id_range.max_id {
+        Some(max_id) => max_id,
+        None => hacker_news::get_max_item_id().await?,
+    };
+    let min_id = std::cmp::max(args.id_range.min_id.unwrap_or(0), max_id - (args.items_num - 1));
+    let worker = Worker::start(&repo, TaskKind::CollectItem, &args.queue);
     // Iterate in reverse order
     let mut chunk_max_id = max_id;
     while chunk_max_id >= min_id {
-        let chunk_min_id = std::cmp::max(min_id, chunk_max_id - chunk_size + 1);
-        collect_chunk_items(Arc::clone(&repo), permits_num, chunk_min_id, chunk_max_id).await?;
-        chunk_max_id -= chunk_size;
+        let chunk_min_id = std::cmp::max(min_id, chunk_max_id - args.chunk_size + 1);
+        if !args.queue.no_enqueue {
+            let mut item_ids = repo
+                .run(move |repo| repo.find_missing_items(chunk_min_id, chunk_max_id))
+                .await?;
+            // Iterate in reverse order
+            item_ids.reverse();
+            worker.enqueue(&repo, item_ids).await?;
+        }
+        loop {
+            let tasks = worker.claim(&repo, args.permits_num).await?;
+            if tasks.is_empty() {
+                break;
+            }
+            let ids = tasks.iter().map(|t| t.item_id).collect::<Vec<i32>>();
+            let mut results = collect_items_by_ids(repo.clone(), args.permits_num, ids).await?;
+            for task in tasks {
+                let result = results.remove(&task.item_id).unwrap_or(Ok(()));
+                worker.finish(&repo, &task, result).await?;
+            }
+        }
+        chunk_max_id -= args.chunk_size;
     }
     Ok(())
 }
 
-pub(crate) async fn collect_item_urls(repo: Arc<Mutex<Repository>>) -> Result<()> {
-    let items_num: i32 = env::var("JOB_COLLECT_ITEM_URLS_NUM")
-        .unwrap_or("1000000".to_string())
-        .parse()?;
-    let permits_num: usize = env::var("JOB_PERMITS_NUM").unwrap_or("10".to_string()).parse()?;
-    let chunk_size: i32 = env::var("JOB_CHUNK_SIZE").unwrap_or("1000".to_string()).parse()?;
-    let replicas_num: i32 = env::var("JOB_REPLICAS_NUM").unwrap_or("1".to_string()).parse()?;
-    let replica_index: i32 = env::var("JOB_REPLICA_INDEX").unwrap_or("0".to_string()).parse()?;
+#[derive(Args)]
+pub(crate) struct FollowItemsArgs {
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 100)]
+    permits_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: i32,
+    /// The interval between polls of the max item id and the updates
+    #[arg(long, env = "JOB_FOLLOW_ITEMS_INTERVAL_SECS", default_value_t = 30)]
+    interval_secs: u64,
+    /// The item id to start from when there's no checkpoint yet, the current max item id by default,
+    /// leaving the older items to collect-items
+    #[arg(long, env = "JOB_FOLLOW_ITEMS_START_ID")]
+    start_id: Option<i32>,
+}
+
+pub(crate) async fn follow_items(repo: Repository, args: FollowItemsArgs) -> Result<()> {
+    loop {
+        match follow_new_and_updated_items(repo.clone(), args.permits_num, args.chunk_size, args.start_id).await {
+            Ok(_) => {}
+            Err(e) => println!("[ERR] main.follow_items: err={e}"),
+        };
+        tokio::time::sleep(Duration::from_secs(args.interval_secs)).await;
+    }
+}
+
+// A single round of `follow_items`, for running it on a schedule
+pub(crate) async fn follow_items_once(repo: Repository, args: FollowItemsArgs) -> Result<()> {
+    follow_new_and_updated_items(repo, args.permits_num, args.chunk_size, args.start_id).await
+}
+
+#[derive(Args)]
+pub(crate) struct RefreshItemsArgs {
+    /// Items posted within this window are refreshed
+    #[arg(long, env = "JOB_REFRESH_ITEMS_WINDOW_SECS", default_value_t = 172800)]
+    window_secs: i64,
+    /// Also refresh the current top stories, however old they are
+    #[arg(long, env = "JOB_REFRESH_TOP_STORIES", value_parser = FalseyValueParser::new())]
+    top_stories: bool,
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 100)]
+    permits_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: usize,
+}
+
+pub(crate) async fn refresh_items(repo: Repository, args: RefreshItemsArgs) -> Result<()> {
+    let min_time = Local::now().timestamp() - args.window_secs;
+    let mut item_ids = repo.run(move |repo| repo.find_refreshable_items(min_time)).await?;
+    if args.top_stories {
+        let top_story_ids = hacker_news::get_top_story_ids().await?;
+        let known_ids: HashSet<i32> = item_ids.iter().copied().collect();
+        item_ids.extend(top_story_ids.into_iter().filter(|id| !known_ids.contains(id)));
+    }
+    for chunk in item_ids.chunks(args.chunk_size) {
+        refresh_chunk_items(repo.clone(), args.permits_num, chunk.to_vec()).await?;
+        println!("[INFO] main.refresh_items: chunk.len={}", chunk.len());
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub(crate) struct CollectItemUrlsArgs {
+    /// The number of items to look for URLs in, counting down from the max item id
+    #[arg(long, env = "JOB_COLLECT_ITEM_URLS_NUM", default_value_t = 1000000)]
+    items_num: i32,
+    #[command(flatten)]
+    id_range: IdRangeArgs,
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 10)]
+    permits_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: i32,
+    #[command(flatten)]
+    fetch: FetchArgs,
+    /// Only print the URLs to fetch
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+// Replicas split the URLs through the task queue, where the URLs of a replica which has died are taken over by others
+pub(crate) async fn collect_item_urls(repo: Repository, args: CollectItemUrlsArgs) -> Result<()> {
+    let browser_pool = Arc::new(new_browser_pool(&args.fetch));
+    let politeness = Arc::new(new_politeness(&args.fetch)?);
+    let worker = Worker::start(&repo, TaskKind::FetchItemUrl, &args.queue);
+    if !args.queue.no_enqueue {
+        enqueue_missing_item_urls(repo.clone(), &args, &worker).await?;
+    }
+    if !args.dry_run {
+        fetch_queued_item_urls(
+            repo.clone(),
+            &worker,
+            Arc::clone(&browser_pool),
+            Arc::clone(&politeness),
+            &args.fetch,
+            args.permits_num,
+        )
+        .await?;
+    }
+    browser_pool.close().await;
+    Ok(())
+}
+
+// Enqueued from the latest items, so that their pages are fetched first
+async fn enqueue_missing_item_urls(repo: Repository, args: &CollectItemUrlsArgs, worker: &Worker) -> Result<()> {
     // TODO: Handle the case where there is no item record
-    let max_id = repo.lock().await.deref_mut().find_max_item_id()?;
-    let min_id = std::cmp::max(
-        max_id - (items_num - 1),
-        repo.lock().await.deref_mut().find_min_item_id()?,
-    );
+    let max_id = match args.id_range.max_id {
+        Some(max_id) => max_id,
+        None => repo.run(|repo| repo.find_max_item_id()).await?,
+    };
+    let min_id = match args.id_range.min_id {
+        Some(min_id) => min_id,
+        None => repo.run(|repo| repo.find_min_item_id()).await?,
+    };
+    let min_id = std::cmp::max(max_id - (args.items_num - 1), min_id);
     // Iterate in reverse order
     let mut chunk_max_id = max_id;
     while chunk_max_id >= min_id {
-        let chunk_min_id = std::cmp::max(min_id, chunk_max_id - chunk_size + 1);
-        collect_chunk_item_urls(
-            Arc::clone(&repo),
-            permits_num,
-            chunk_min_id,
-            chunk_max_id,
-            replicas_num,
-            replica_index,
+        let chunk_min_id = std::cmp::max(min_id, chunk_max_id - args.chunk_size + 1);
+        let item_urls = repo
+            .run(move |repo| repo.find_missing_item_urls(chunk_min_id, chunk_max_id))
+            .await?
+            .into_iter()
+            // Iterate in reverse order
+            .rev()
+            .map(|(id, url)| (id, url, 1))
+            .collect::<Vec<(i32, String, i32)>>();
+        if args.dry_run {
+            print_item_urls(&item_urls);
+        } else {
+            let ids = item_urls.iter().map(|(id, _, _)| *id).collect::<Vec<i32>>();
+            worker.enqueue(&repo, ids).await?;
+        }
+        chunk_max_id -= args.chunk_size;
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub(crate) struct RetryItemUrlsArgs {
+    /// The number of failed URLs to retry
+    #[arg(long, env = "JOB_RETRY_ITEM_URLS_NUM", default_value_t = 1000)]
+    items_num: usize,
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 10)]
+    permits_num: usize,
+    #[command(flatten)]
+    fetch: FetchArgs,
+    /// Only print the URLs to retry
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn retry_item_urls(repo: Repository, args: RetryItemUrlsArgs) -> Result<()> {
+    let browser_pool = Arc::new(new_browser_pool(&args.fetch));
+    let politeness = Arc::new(new_politeness(&args.fetch)?);
+    let worker = Worker::start(&repo, TaskKind::FetchItemUrl, &args.queue);
+    if !args.queue.no_enqueue {
+        let (max_attempts_num, items_num) = (args.fetch.max_attempts_num, args.items_num);
+        let item_urls = repo
+            .run(move |repo| repo.find_retryable_item_urls(max_attempts_num, items_num))
+            .await?
+            .into_iter()
+            .map(|(id, url, attempts_num)| (id, url, attempts_num + 1))
+            .collect::<Vec<(i32, String, i32)>>();
+        if args.dry_run {
+            print_item_urls(&item_urls);
+        } else {
+            let ids = item_urls.iter().map(|(id, _, _)| *id).collect::<Vec<i32>>();
+            worker.enqueue(&repo, ids).await?;
+        }
+    }
+    if !args.dry_run {
+        fetch_queued_item_urls(
+            repo.clone(),
+            &worker,
+            Arc::clone(&browser_pool),
+            politeness,
+            &args.fetch,
+            args.permits_num,
         )
         .await?;
-        chunk_max_id -= chunk_size;
     }
+    browser_pool.close().await;
     Ok(())
 }
 
-pub(crate) async fn summarize_texts(mut repo: Repository) -> Result<()> {
-    let texts_num: usize = env::var("JOB_SUMMARIZE_TEXTS_NUM")
-        .unwrap_or("30".to_string())
-        .parse()?;
-    let top_story_ids = hacker_news::get_top_story_ids().await?;
-    let mut items = repo.find_summary_missing_items(&top_story_ids)?;
-    // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
-    //   as `LIMIT` doesn't maintain the order of top stories' ids.
-    items.truncate(texts_num);
-    if env::var("JOB_SUMMARIZE_ADDITIONAL_TEXTS").is_ok() && items.len() < texts_num {
-        let mut additional_items =
-            repo.find_summary_missing_items_excluding(&top_story_ids, texts_num - items.len())?;
-        items.append(&mut additional_items);
-    }
-    for (id, title, text) in items {
-        let shortened_text = command::shorten_text(&text)?;
-        let start_time = std::time::Instant::now();
-        let summary = match inference::instruct_summary(&title, &shortened_text).await {
-            Ok(summary) => summary,
-            Err(e) => {
-                println!("[ERR] inference.instruct_summary (id={id}): err={e}");
-                continue;
+#[derive(Args)]
+pub(crate) struct ReextractItemUrlsArgs {
+    /// The smallest item id to process
+    #[arg(long, env = "JOB_REEXTRACT_MIN_ID")]
+    min_id: Option<i32>,
+    /// The largest item id to process
+    #[arg(long, env = "JOB_REEXTRACT_MAX_ID")]
+    max_id: Option<i32>,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: i32,
+    /// Also clear the summaries, analyses and embeddings of the changed texts, so that they are recomputed
+    #[arg(long, env = "JOB_REEXTRACT_INVALIDATE", value_parser = FalseyValueParser::new())]
+    invalidate: bool,
+    #[arg(long, env = "SEARCH_ENGINE_VECTOR_SUMMARY_COLLECTION_NAME")]
+    summary_collection_name: Option<String>,
+    #[arg(long, env = "SEARCH_ENGINE_VECTOR_KEYWORD_COLLECTION_NAME")]
+    keyword_collection_name: Option<String>,
+    /// Only print the ids whose texts would change
+    #[arg(long)]
+    dry_run: bool,
+}
+
+// Applies the current text extraction to the stored HTML, e.g. after the extraction has been improved
+pub(crate) async fn reextract_item_urls(repo: Repository, args: ReextractItemUrlsArgs) -> Result<()> {
+    let min_id = match args.min_id {
+        Some(min_id) => min_id,
+        None => repo.run(|repo| repo.find_min_item_id()).await?,
+    };
+    let max_id = match args.max_id {
+        Some(max_id) => max_id,
+        None => repo.run(|repo| repo.find_max_item_id()).await?,
+    };
+    // A dry run never touches the search engine
+    let collection_names = if args.invalidate && !args.dry_run {
+        match (args.summary_collection_name, args.keyword_collection_name) {
+            (Some(summary_collection_name), Some(keyword_collection_name)) => {
+                vec![summary_collection_name, keyword_collection_name]
             }
-        };
+            _ => bail!("Both collection names are needed to invalidate embeddings"),
+        }
+    } else {
+        vec![]
+    };
+    let mut chunk_min_id = min_id;
+    while chunk_min_id <= max_id {
+        let chunk_max_id = std::cmp::min(max_id, chunk_min_id + args.chunk_size - 1);
+        let (invalidate, dry_run) = (args.invalidate, args.dry_run);
+        let updated_ids = repo
+            .run(move |repo| reextract_chunk_item_urls(repo, chunk_min_id, chunk_max_id, invalidate, dry_run))
+            .await?;
+        if !args.dry_run && !updated_ids.is_empty() {
+            for collection_name in collection_names.iter() {
+                search_engine::delete(collection_name.clone(), updated_ids.clone()).await?;
+            }
+        }
         println!(
-            "[INFO] main.summarize_texts (id={}): shortened_text.len={}, summary.len={}, elapsed_time={:?}",
-            id,
-            shortened_text.len(),
-            summary.len(),
-            start_time.elapsed()
+            "[INFO] main.reextract_item_urls: chunk_min_id={}, chunk_max_id={}, updated_ids.len={}",
+            chunk_min_id,
+            chunk_max_id,
+            updated_ids.len()
         );
-        repo.update_item_url(id, summary)?;
+        chunk_min_id += args.chunk_size;
     }
     Ok(())
 }
 
-pub(crate) async fn embed_summaries(mut repo: Repository) -> Result<()> {
-    let collection_name = env::var("SEARCH_ENGINE_VECTOR_SUMMARY_COLLECTION_NAME")?;
-    let summaries_num: usize = env::var("JOB_EMBED_SUMMARIES_NUM")
-        .unwrap_or("1000000".to_string())
-        .parse()?;
-    let chunk_size: usize = env::var("JOB_CHUNK_SIZE").unwrap_or("50".to_string()).parse()?;
-    let summary_existing_ids = repo.find_summary_existing_items(summaries_num)?;
-    let embedding_missing_ids = search_engine::find_missing(collection_name.clone(), summary_existing_ids).await?;
-    for chunk in embedding_missing_ids.chunks(chunk_size) {
-        let item_summaries = repo.find_item_summaries(chunk)?;
-        for (id, text, summary) in item_summaries {
-            let sentence = if let Some(text) = text {
-                text
-            } else if let Some(summary) = summary {
-                summary
-            } else {
+// Extracting texts and updating them are both blocking, so a whole chunk is done on a blocking thread
+fn reextract_chunk_item_urls(
+    repo: &Repository,
+    chunk_min_id: i32,
+    chunk_max_id: i32,
+    invalidate: bool,
+    dry_run: bool,
+) -> Result<Vec<i32>> {
+    let mut updated_ids = vec![];
+    for (id, text) in repo.find_reextractable_item_urls(chunk_min_id, chunk_max_id)? {
+        let html = match repo.find_item_url_html(id) {
+            Ok(Some(html)) => html,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("[ERR] repo.find_item_url_html (id={id}): err={e}");
+                continue;
+            }
+        };
+        let (new_text, metadata) = match hacker_news::extract_text(&html) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                println!("[ERR] hacker_news.extract_text (id={id}): err={e}");
                 continue;
+            }
+        };
+        if text.as_deref() == Some(new_text.as_str()) {
+            continue;
+        }
+        updated_ids.push(id);
+        if dry_run {
+            println!(
+                "[INFO] main.reextract_item_urls (id={}): text.len={}, new_text.len={}",
+                id,
+                text.map(|t| t.len()).unwrap_or(0),
+                new_text.len()
+            );
+            continue;
+        }
+        let content_hash = canonicalizer::hash_content(&new_text);
+        repo.update_item_url_text(id, new_text, metadata, content_hash)?;
+        if invalidate {
+            repo.invalidate_item_url_summary(id)?;
+            repo.delete_analysis(id)?;
+        }
+    }
+    Ok(updated_ids)
+}
+
+#[derive(Args)]
+pub(crate) struct SummarizeTextsArgs {
+    /// The number of top stories to summarize
+    #[arg(long, env = "JOB_SUMMARIZE_TEXTS_NUM", default_value_t = 30)]
+    texts_num: usize,
+    /// Fill up the rest with the latest stories, when there are not enough top stories to summarize
+    #[arg(long, env = "JOB_SUMMARIZE_ADDITIONAL_TEXTS", value_parser = FalseyValueParser::new())]
+    additional_texts: bool,
+    /// The number of tokens kept for the completion out of the context size of the inference server
+    #[arg(long, env = "JOB_SUMMARIZE_COMPLETION_TOKENS_NUM", default_value_t = 1024)]
+    completion_tokens_num: usize,
+    /// Also summarize again the texts whose summaries were generated by older versions of the prompts,
+    /// clearing the embeddings and the summary passages of the summaries which are replaced
+    #[arg(long, env = "JOB_SUMMARIZE_OUTDATED", value_parser = FalseyValueParser::new())]
+    outdated: bool,
+    #[arg(long, env = "SEARCH_ENGINE_VECTOR_SUMMARY_COLLECTION_NAME")]
+    summary_collection_name: Option<String>,
+    /// The number of completions tried for a summary before it's rejected
+    #[arg(long, env = "JOB_INSTRUCT_SUMMARY_MAX_ATTEMPTS_NUM", default_value_t = 3)]
+    max_attempts_num: usize,
+    /// Summaries with fewer chars are rejected
+    #[arg(long, env = "JOB_SUMMARY_MIN_LEN", default_value_t = 80)]
+    min_summary_len: usize,
+    /// Summaries with more chars are rejected
+    #[arg(long, env = "JOB_SUMMARY_MAX_LEN", default_value_t = 6000)]
+    max_summary_len: usize,
+    /// Summaries with a lower rate of common English words are rejected
+    #[arg(long, env = "JOB_SUMMARY_MIN_ENGLISH_RATE", default_value_t = 0.15)]
+    min_english_rate: f64,
+    /// Summaries with a lower rate of content words found in their source are rejected
+    #[arg(long, env = "JOB_SUMMARY_MIN_SOURCE_OVERLAP", default_value_t = 0.3)]
+    min_source_overlap: f64,
+    /// The directory of the prompts, "<id>/v<version>.txt"
+    #[arg(long, env = "JOB_PROMPTS_DIR", default_value = "prompts")]
+    prompts_dir: PathBuf,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn summarize_texts(repo: Repository, args: SummarizeTextsArgs) -> Result<()> {
+    let worker = Worker::start(&repo, TaskKind::SummarizeText, &args.queue);
+    let prompts = prompt::find_keys(&args.prompts_dir, &inference::SUMMARY_PROMPTS)?;
+    let collection_name = match (args.outdated, &args.summary_collection_name) {
+        (false, _) => None,
+        (true, Some(summary_collection_name)) => Some(summary_collection_name.as_str()),
+        (true, None) => bail!("The summary collection name is needed to invalidate embeddings"),
+    };
+    if !args.queue.no_enqueue {
+        let top_story_ids = hacker_news::get_top_story_ids().await?;
+        let ids = top_story_ids.clone();
+        let outdated_prompts = args.outdated.then(|| prompts.clone());
+        let mut items = repo
+            .run(move |repo| repo.find_summary_missing_items(&ids, outdated_prompts.as_deref()))
+            .await?;
+        // NOTE: We must use `truncate` function here instead of `LIMIT` in the query,
+        //   as `LIMIT` doesn't maintain the order of top stories' ids.
+        items.truncate(args.texts_num);
+        if args.additional_texts && items.len() < args.texts_num {
+            let limit = args.texts_num - items.len();
+            let outdated_prompts = args.outdated.then(|| prompts.clone());
+            let mut additional_items = repo
+                .run(move |repo| {
+                    repo.find_summary_missing_items_excluding(&top_story_ids, limit, outdated_prompts.as_deref())
+                })
+                .await?;
+            items.append(&mut additional_items);
+        }
+        // Duplicates share their summary, so the same text is summarized only once
+        let mut content_hashes = HashSet::new();
+        let ids = items
+            .into_iter()
+            .filter(|(_, _, text)| content_hashes.insert(canonicalizer::hash_content(text)))
+            .map(|(id, _, _)| id)
+            .collect::<Vec<i32>>();
+        worker.enqueue(&repo, ids).await?;
+    }
+    // Asked for on the first claimed task, so that a run with nothing to summarize doesn't need the inference server
+    let mut chunk_len = None;
+    let checks = SummaryChecks {
+        min_len: args.min_summary_len,
+        max_len: args.max_summary_len,
+        min_english_rate: args.min_english_rate,
+        min_source_overlap: args.min_source_overlap,
+    };
+    loop {
+        let tasks = worker.claim(&repo, 1).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        for task in tasks {
+            let result = async {
+                let chunk_len = match chunk_len {
+                    Some(chunk_len) => chunk_len,
+                    None => *chunk_len.insert(summary_chunk_len(args.completion_tokens_num).await?),
+                };
+                summarize_text(
+                    &repo,
+                    task.item_id,
+                    chunk_len,
+                    &checks,
+                    &args,
+                    &prompts,
+                    collection_name,
+                )
+                .await
+            }
+            .await;
+            worker.finish(&repo, &task, result).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn summarize_text(
+    repo: &Repository,
+    id: i32,
+    chunk_len: usize,
+    checks: &SummaryChecks,
+    args: &SummarizeTextsArgs,
+    prompts: &[String],
+    collection_name: Option<&str>,
+) -> Result<()> {
+    // Summarized in the meantime, e.g. as a duplicate of another item
+    let current_prompts = prompts.to_vec();
+    let (_, title, text) = match repo
+        .run(move |repo| repo.find_summary_missing_items(&[id], Some(&current_prompts)))
+        .await?
+        .pop()
+    {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    let chunks = command::split_text(&text, chunk_len);
+    let chunks_num = chunks.len();
+    let start_time = std::time::Instant::now();
+    let mut generations = vec![];
+    let summary = summarize_chunks(
+        &args.prompts_dir,
+        &title,
+        chunks,
+        chunk_len,
+        checks,
+        args.max_attempts_num,
+        &mut generations,
+    )
+    .await;
+    repo.run(move |repo| repo.insert_generations(id, generations)).await?;
+    // Recorded instead of the summary, so that it's neither embedded nor summarized again
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(e) => match e.downcast::<SummaryRejection>() {
+            Ok(rejection) => {
+                println!(
+                    "[INFO] main.summarize_texts (id={}): rejection.reason={}, elapsed_time={:?}",
+                    id,
+                    rejection.reason,
+                    start_time.elapsed()
+                );
+                let prompts = prompts.to_vec();
+                let rejected_ids = repo
+                    .run(move |repo| repo.reject_item_url_summary(id, rejection.reason, prompts))
+                    .await?;
+                invalidate_summaries(repo, collection_name, rejected_ids).await?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    println!(
+        "[INFO] main.summarize_texts (id={}): text.len={}, chunks_num={}, topics_num={}, summary.len={}, elapsed_time={:?}",
+        id,
+        text.len(),
+        chunks_num,
+        summary.topics.len(),
+        summary.summary.len(),
+        start_time.elapsed()
+    );
+    let prompts = prompts.to_vec();
+    let updated_ids = repo.run(move |repo| repo.update_item_url(id, summary, prompts)).await?;
+    invalidate_summaries(repo, collection_name, updated_ids).await?;
+    Ok(())
+}
+
+// Like `reextract_item_urls`, the embeddings and the summary passages of the replaced summaries are cleared,
+// so that they are computed again from the new ones
+async fn invalidate_summaries(repo: &Repository, collection_name: Option<&str>, ids: Vec<i32>) -> Result<()> {
+    let collection_name = match collection_name {
+        Some(collection_name) => collection_name,
+        None => return Ok(()),
+    };
+    search_engine::delete(collection_name.to_string(), ids.clone()).await?;
+    repo.run(move |repo| repo.invalidate_summary_passages(&ids)).await?;
+    Ok(())
+}
+
+// Map-reduce, i.e. every chunk is summarized on its own, then the partial summaries are merged until one is left
+async fn summarize_chunks(
+    prompts_dir: &Path,
+    title: &str,
+    chunks: Vec<String>,
+    chunk_len: usize,
+    checks: &SummaryChecks,
+    max_attempts_num: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<Summary> {
+    let mut summaries = vec![];
+    for chunk in chunks {
+        summaries.push(
+            inference::instruct_summary(prompts_dir, title, &chunk, checks, max_attempts_num, generations).await?,
+        );
+    }
+    while summaries.len() > 1 {
+        let mut merged_summaries = vec![];
+        for group in group_summaries(summaries, chunk_len) {
+            merged_summaries.push(
+                inference::instruct_merged_summary(prompts_dir, title, &group, checks, max_attempts_num, generations)
+                    .await?,
+            );
+        }
+        summaries = merged_summaries;
+    }
+    match summaries.pop() {
+        Some(summary) => Ok(summary),
+        None => bail!("Text has nothing to summarize"),
+    }
+}
+
+// Every group has at least two summaries, so that each round of merging reduces the number of summaries
+fn group_summaries(summaries: Vec<Summary>, max_len: usize) -> Vec<Vec<Summary>> {
+    let mut groups: Vec<Vec<Summary>> = vec![];
+    let mut group_len = 0;
+    for summary in summaries {
+        match groups.last_mut() {
+            Some(group) if group.len() < 2 || group_len + summary.summary.len() <= max_len => {
+                group_len += summary.summary.len();
+                group.push(summary);
+            }
+            _ => {
+                group_len = summary.summary.len();
+                groups.push(vec![summary]);
+            }
+        }
+    }
+    // The last summary left alone is merged into the previous group
+    if groups.len() > 1 && groups.last().map(|g| g.len()) == Some(1) {
+        if let Some(mut group) = groups.pop() {
+            if let Some(previous_group) = groups.last_mut() {
+                previous_group.append(&mut group);
+            }
+        }
+    }
+    groups
+}
+
+// The length of a chunk in bytes, where a token is estimated to be at least `CHARS_PER_TOKEN` bytes long
+async fn summary_chunk_len(completion_tokens_num: usize) -> Result<usize> {
+    let context_size = inference::get_context_size().await?;
+    let chunk_tokens_num = context_size.saturating_sub(completion_tokens_num + SUMMARY_PROMPT_TOKENS_NUM);
+    let chunk_len = chunk_tokens_num * CHARS_PER_TOKEN;
+    if chunk_len < MIN_SUMMARY_CHUNK_LEN {
+        bail!(
+            "Context size of the inference server is too small to summarize texts: {}",
+            context_size
+        );
+    }
+    Ok(chunk_len)
+}
+
+// The tokens taken by the instruction and the title
+const SUMMARY_PROMPT_TOKENS_NUM: usize = 256;
+
+// A conservative estimate for English text, which averages around 4 bytes per token
+const CHARS_PER_TOKEN: usize = 3;
+
+const MIN_SUMMARY_CHUNK_LEN: usize = 1000;
+
+#[derive(Args)]
+pub(crate) struct EmbedSummariesArgs {
+    #[arg(long, env = "SEARCH_ENGINE_VECTOR_SUMMARY_COLLECTION_NAME")]
+    collection_name: String,
+    /// The number of the latest summaries to embed if missing
+    #[arg(long, env = "JOB_EMBED_SUMMARIES_NUM", default_value_t = 1000000)]
+    summaries_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 50)]
+    chunk_size: usize,
+    #[command(flatten)]
+    queue: QueueArgs,
+}
+
+pub(crate) async fn embed_summaries(repo: Repository, args: EmbedSummariesArgs) -> Result<()> {
+    let collection_name = args.collection_name;
+    let worker = Worker::start(&repo, TaskKind::EmbedSummary, &args.queue);
+    if !args.queue.no_enqueue {
+        let summaries_num = args.summaries_num;
+        let summary_existing_ids = repo
+            .run(move |repo| repo.find_summary_existing_items(summaries_num))
+            .await?;
+        let embedding_missing_ids = search_engine::find_missing(collection_name.clone(), summary_existing_ids).await?;
+        worker.enqueue(&repo, embedding_missing_ids).await?;
+    }
+    loop {
+        let tasks = worker.claim(&repo, args.chunk_size).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        let ids = tasks.iter().map(|t| t.item_id).collect::<Vec<i32>>();
+        let mut item_summaries = repo
+            .run(move |repo| repo.find_item_summaries(&ids))
+            .await?
+            .into_iter()
+            .map(|(id, text, summary)| (id, text.or(summary)))
+            .collect::<HashMap<i32, Option<String>>>();
+        for task in tasks {
+            let result = match item_summaries.remove(&task.item_id).flatten() {
+                Some(sentence) => embed_summary(&collection_name, task.item_id, sentence).await,
+                None => Ok(()),
             };
-            let embedding = inference::embed(sentence).await?;
-            search_engine::upsert(collection_name.clone(), id, embedding).await?;
-            println!("[INFO] main.embed_summaries (id={})", id);
+            worker.finish(&repo, &task, result).await?;
         }
     }
     Ok(())
 }
 
+async fn embed_summary(collection_name: &str, id: i32, sentence: String) -> Result<()> {
+    let embedding = inference::embed(sentence).await?;
+    search_engine::upsert(collection_name.to_string(), id, embedding).await?;
+    println!("[INFO] main.embed_summaries (id={})", id);
+    Ok(())
+}
+
+// Walks forward from the checkpoint to the current max item id, then re-fetches items listed in the updates feed
+async fn follow_new_and_updated_items(
+    repo: Repository,
+    permits_num: usize,
+    chunk_size: i32,
+    start_id: Option<i32>,
+) -> Result<()> {
+    let max_id = hacker_news::get_max_item_id().await?;
+    let checkpoint = repo.run(|repo| repo.find_checkpoint(FOLLOW_ITEMS_CHECKPOINT)).await?;
+    let mut chunk_min_id = match checkpoint {
+        Some(id) => id + 1,
+        None => {
+            let start_id = start_id.unwrap_or(max_id);
+            println!("[INFO] main.follow_items: no checkpoint, start_id={start_id}");
+            start_id
+        }
+    };
+    while chunk_min_id <= max_id {
+        let chunk_max_id = std::cmp::min(max_id, chunk_min_id + chunk_size - 1);
+        let failed_ids = collect_chunk_items(repo.clone(), permits_num, chunk_min_id, chunk_max_id).await?;
+        // The checkpoint stops short of the failed items, which are collected again by the next round,
+        // while the items collected after them are skipped as they're not missing any more
+        let checkpoint = match failed_ids.iter().min() {
+            Some(failed_id) => failed_id - 1,
+            None => chunk_max_id,
+        };
+        repo.run(move |repo| repo.upsert_checkpoint(FOLLOW_ITEMS_CHECKPOINT, checkpoint))
+            .await?;
+        if !failed_ids.is_empty() {
+            println!("[INFO] main.follow_items: failed_ids={failed_ids:?}, checkpoint={checkpoint}");
+            break;
+        }
+        chunk_min_id += chunk_size;
+    }
+    let updates = hacker_news::get_updates().await?;
+    println!(
+        "[INFO] main.follow_items: max_id={}, updated_items.len={}, updated_profiles.len={}",
+        max_id,
+        updates.items.len(),
+        updates.profiles.len()
+    );
+    refresh_chunk_items(repo.clone(), permits_num, updates.items).await?;
+    command::user::collect_chunk_users(repo.clone(), permits_num, updates.profiles).await?;
+    Ok(())
+}
+
+// Returns the ids of the items which failed to be collected
 async fn collect_chunk_items(
-    repo: Arc<Mutex<Repository>>,
+    repo: Repository,
     permits_num: usize,
     chunk_min_id: i32,
     chunk_max_id: i32,
-) -> Result<()> {
+) -> Result<Vec<i32>> {
+    let mut item_ids = repo
+        .run(move |repo| repo.find_missing_items(chunk_min_id, chunk_max_id))
+        .await?;
+    // Iterate in reverse order
+    item_ids.reverse();
+    let mut failed_ids = vec![];
+    for (id, result) in collect_items_by_ids(repo, permits_num, item_ids).await? {
+        match result {
+            Ok(_) => {}
+            Err(e) => {
+                println!("[ERR] main.collect_chunk_items.handle (id={id}): err={e}");
+                failed_ids.push(id);
+            }
+        };
+    }
+    Ok(failed_ids)
+}
+
+async fn collect_items_by_ids(
+    repo: Repository,
+    permits_num: usize,
+    item_ids: Vec<i32>,
+) -> Result<HashMap<i32, Result<()>>> {
     let semaphore = Arc::new(Semaphore::new(permits_num));
     let mut handles = HashMap::new();
-    let item_ids = Arc::clone(&repo)
-        .lock()
-        .await
-        .deref_mut()
-        .find_missing_items(chunk_min_id, chunk_max_id)?;
-    // Iterate in reverse order
-    for id in item_ids.into_iter().rev() {
+    for id in item_ids {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let repo_inst = repo.clone();
+        let handle: JoinHandle<Result<()>> = tokio::spawn(async move {
+            let item = match command::retry(|| hacker_news::get_item(id)).await {
+                Ok(item) => item,
+                Err(e) => bail!(e),
+            };
+            match repo_inst.run(move |repo| repo.insert_item(item)).await {
+                Ok(_) => {}
+                Err(e) => bail!(e),
+            };
+            drop(permit);
+            Ok(())
+        });
+        handles.insert(id, handle);
+    }
+    let mut results = HashMap::new();
+    for (id, handle) in handles {
+        results.insert(id, handle.await?);
+    }
+    Ok(results)
+}
+
+async fn refresh_chunk_items(repo: Repository, permits_num: usize, item_ids: Vec<i32>) -> Result<()> {
+    let semaphore = Arc::new(Semaphore::new(permits_num));
+    let mut handles = HashMap::new();
+    for id in item_ids {
         let permit = semaphore.clone().acquire_owned().await?;
-        let repo_inst = Arc::clone(&repo);
+        let repo_inst = repo.clone();
         let handle = tokio::spawn(async move {
-            let max_retry_count = 100;
-            let mut retry_count = 0;
-            let item = loop {
-                match hacker_news::get_item(id).await {
-                    Ok(item) => break item,
-                    Err(e) => {
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        retry_count += 1;
-                        if retry_count >= max_retry_count {
-                            bail!(e)
-                        }
-                        continue;
-                    }
-                }
+            let item = match command::retry(|| hacker_news::get_item(id)).await {
+                Ok(item) => item,
+                Err(e) => bail!(e),
             };
-            match repo_inst.lock().await.deref_mut().insert_item(item) {
+            match repo_inst.run(move |repo| repo.upsert_item(item)).await {
                 Ok(_) => {}
-                Err(e) => println!("[ERR] repo.insert_item (id={id}): err={e}"),
+                Err(e) => println!("[ERR] repo.upsert_item (id={id}): err={e}"),
             };
             drop(permit);
             Ok(())
@@ -166,52 +889,320 @@ async fn collect_chunk_items(
     for (id, handle) in handles {
         match handle.await? {
             Ok(_) => {}
-            Err(e) => println!("[ERR] main.collect_chunk_items.handle (id={id}): err={e}"),
+            Err(e) => println!("[ERR] main.refresh_chunk_items.handle (id={id}): err={e}"),
         };
     }
     Ok(())
 }
 
-async fn collect_chunk_item_urls(
-    repo: Arc<Mutex<Repository>>,
+async fn fetch_item_urls(
+    repo: Repository,
+    browser_pool: Arc<BrowserPool>,
+    politeness: Arc<Politeness>,
+    options: Arc<FetchOptions>,
     permits_num: usize,
-    chunk_min_id: i32,
-    chunk_max_id: i32,
-    replicas_num: i32,
-    replica_index: i32,
-) -> Result<()> {
+    retry_base_secs: i64,
+    item_urls: Vec<(i32, String, i32)>,
+) -> Result<HashMap<i32, Result<()>>> {
     let semaphore = Arc::new(Semaphore::new(permits_num));
     let mut handles = HashMap::new();
-    let item_urls = Arc::clone(&repo)
-        .lock()
-        .await
-        .deref_mut()
-        .find_missing_item_urls(chunk_min_id, chunk_max_id)?;
-    // Iterate in reverse order
-    for (id, url) in item_urls.into_iter().rev() {
-        if id % replicas_num != replica_index {
-            continue;
-        }
+    for (id, url, attempts_num) in item_urls {
         let permit = semaphore.clone().acquire_owned().await?;
-        let repo_inst = Arc::clone(&repo);
-        let handle = tokio::spawn(tokio::time::timeout(Duration::from_secs(300), async move {
-            let item_url = match hacker_news::get_item_url(&url).await {
-                Ok(item_url) => item_url,
-                Err(e) => service::ItemUrl::Canceled { note: e.to_string() },
+        let repo_inst = repo.clone();
+        let browser_pool_inst = Arc::clone(&browser_pool);
+        let politeness_inst = Arc::clone(&politeness);
+        let options_inst = Arc::clone(&options);
+        let handle: JoinHandle<Result<()>> = tokio::spawn(async move {
+            let normalized_url = canonicalizer::normalize_url(&url);
+            if let Some(normalized_url) = normalized_url.clone() {
+                match repo_inst
+                    .run(move |repo| reuse_item_url(repo, id, &normalized_url, attempts_num))
+                    .await
+                {
+                    Ok(true) => {
+                        drop(permit);
+                        return Ok(());
+                    }
+                    Ok(false) => {}
+                    Err(e) => println!("[ERR] repo.reuse_item_url (id={id}): err={e}"),
+                };
+            }
+            let item_url = match tokio::time::timeout(
+                Duration::from_secs(300),
+                hacker_news::get_item_url(&url, &browser_pool_inst, &politeness_inst, &options_inst),
+            )
+            .await
+            {
+                Ok(Ok(item_url)) => item_url,
+                Ok(Err(e)) => ItemUrl::Failed {
+                    failure: ItemUrlFailure::from_error(&e),
+                    note: e.to_string(),
+                },
+                Err(e) => ItemUrl::Failed {
+                    failure: ItemUrlFailure::Timeout,
+                    note: e.to_string(),
+                },
+            };
+            // A deferral is no failure of the URL, so it's retried once the domain is ready without using up an attempt
+            let (attempts_num, next_attempt_at) = match &item_url {
+                ItemUrl::Failed {
+                    failure: ItemUrlFailure::Deferred(deferral),
+                    ..
+                } => (
+                    attempts_num - 1,
+                    Some(Local::now() + chrono::Duration::from_std(*deferral)?),
+                ),
+                ItemUrl::Failed { failure, .. } if failure.is_transient() => {
+                    (attempts_num, Some(next_attempt_at(retry_base_secs, attempts_num)))
+                }
+                _ => (attempts_num, None),
+            };
+            let (content_hash, matches_content) = match &item_url {
+                ItemUrl::Finished { text, .. } => (
+                    Some(canonicalizer::hash_content(text)),
+                    text.len() >= options_inst.min_duplicate_text_len,
+                ),
+                _ => (None, false),
             };
-            match repo_inst.lock().await.deref_mut().insert_item_url(id, item_url) {
+            match repo_inst
+                .run(move |repo| repo.upsert_item_url(id, item_url, attempts_num, next_attempt_at))
+                .await
+            {
                 Ok(_) => {}
-                Err(e) => println!("[ERR] repo.insert_item_url (id={id}): err={e}"),
+                Err(e) => bail!(e),
             };
+            if content_hash.is_some() {
+                match repo_inst
+                    .run(move |repo| {
+                        link_duplicate_item_url(
+                            repo,
+                            id,
+                            normalized_url.as_deref(),
+                            content_hash.as_deref(),
+                            matches_content,
+                        )
+                    })
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) => println!("[ERR] repo.link_duplicate_item_url (id={id}): err={e}"),
+                };
+            }
             drop(permit);
-        }));
+            Ok(())
+        });
         handles.insert(id, handle);
     }
+    let mut results = HashMap::new();
     for (id, handle) in handles {
-        match handle.await? {
-            Ok(_) => {}
-            Err(e) => println!("[ERR] main.collect_chunk_item_urls.handle (id={id}): err={e}"),
-        };
+        results.insert(id, handle.await?);
+    }
+    Ok(results)
+}
+
+// Claims only as many URLs as can be fetched at once, which leaves the rest to other replicas
+async fn fetch_queued_item_urls(
+    repo: Repository,
+    worker: &Worker,
+    browser_pool: Arc<BrowserPool>,
+    politeness: Arc<Politeness>,
+    args: &FetchArgs,
+    permits_num: usize,
+) -> Result<()> {
+    let options = Arc::new(new_fetch_options(args));
+    loop {
+        let tasks = worker.claim(&repo, permits_num).await?;
+        if tasks.is_empty() {
+            break;
+        }
+        let ids = tasks.iter().map(|t| t.item_id).collect::<Vec<i32>>();
+        let max_attempts_num = args.max_attempts_num;
+        let item_urls = repo
+            .run(move |repo| repo.find_fetchable_item_urls(&ids, max_attempts_num))
+            .await?;
+        let mut results = fetch_item_urls(
+            repo.clone(),
+            Arc::clone(&browser_pool),
+            Arc::clone(&politeness),
+            Arc::clone(&options),
+            permits_num,
+            args.retry_base_secs,
+            item_urls,
+        )
+        .await?;
+        for task in tasks {
+            // Fetched in the meantime, or not a story with a URL at all
+            let result = results.remove(&task.item_id).unwrap_or(Ok(()));
+            worker.finish(&repo, &task, result).await?;
+        }
     }
     Ok(())
 }
+
+fn reuse_item_url(repo: &Repository, id: i32, normalized_url: &str, attempts_num: i32) -> Result<bool> {
+    let original_id = match repo.reuse_item_url(id, normalized_url, attempts_num)? {
+        Some(original_id) => original_id,
+        None => return Ok(false),
+    };
+    repo.copy_analysis(id, original_id)?;
+    println!("[INFO] main.reuse_item_url (id={id}): original_id={original_id}");
+    Ok(true)
+}
+
+fn link_duplicate_item_url(
+    repo: &Repository,
+    id: i32,
+    normalized_url: Option<&str>,
+    content_hash: Option<&str>,
+    matches_content: bool,
+) -> Result<()> {
+    if let Some(original_id) = repo.link_duplicate_item_url(id, normalized_url, content_hash, matches_content)? {
+        repo.copy_analysis(id, original_id)?;
+        println!("[INFO] main.link_duplicate_item_url (id={id}): original_id={original_id}");
+    }
+    Ok(())
+}
+
+// Exponential backoff, i.e. the waiting time doubles after each failed attempt
+fn next_attempt_at(base_secs: i64, attempts_num: i32) -> DateTime<Local> {
+    let delay_secs = base_secs.saturating_mul(1 << std::cmp::min(attempts_num - 1, 20));
+    Local::now() + chrono::Duration::seconds(delay_secs)
+}
+
+fn print_item_urls(item_urls: &[(i32, String, i32)]) {
+    for (id, url, attempts_num) in item_urls {
+        println!("[INFO] main.print_item_urls (id={id}): url={url}, attempts_num={attempts_num}");
+    }
+}
+
+fn new_browser_pool(args: &FetchArgs) -> BrowserPool {
+    BrowserPool::new(args.browsers_num, args.browser_max_pages_num, &user_agent(args))
+}
+
+fn new_politeness(args: &FetchArgs) -> Result<Politeness> {
+    Politeness::new(&user_agent(args), args.domain_permits_num, args.domain_requests_per_sec)
+}
+
+fn new_fetch_options(args: &FetchArgs) -> FetchOptions {
+    FetchOptions {
+        pdf_max_size: args.pdf_max_size,
+        html_max_size: args.html_max_size,
+        browser_domains: args.browser_domains.clone(),
+        min_words_count: args.min_words_count,
+        min_duplicate_text_len: args.min_duplicate_text_len,
+    }
+}
+
+fn user_agent(args: &FetchArgs) -> String {
+    match &args.user_agent {
+        Some(user_agent) => user_agent.clone(),
+        None => format!("newswaters/{}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::{
+        repository::tests::{connect, delete_checkpoint, delete_items},
+        service::hacker_news::tests::{stand_in, STAND_IN_FAILING_ID, STAND_IN_ITEM_IDS as IDS},
+    };
+
+    async fn find_missing_items(repo: &Repository) -> Vec<i32> {
+        let mut ids = repo.run(|repo| repo.find_missing_items(IDS[0], IDS[4])).await.unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn follows_new_and_updated_items() {
+        let Some(repo) = connect() else { return };
+        stand_in();
+
+        // The first run starts from the max item, and the updated item is collected along with it
+        delete_items(&repo, &IDS);
+        delete_checkpoint(&repo, FOLLOW_ITEMS_CHECKPOINT);
+        follow_new_and_updated_items(repo.clone(), 10, 2, None).await.unwrap();
+        assert_eq!(find_missing_items(&repo).await, IDS[1..4]);
+        let checkpoint = repo
+            .run(|repo| repo.find_checkpoint(FOLLOW_ITEMS_CHECKPOINT))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint, Some(IDS[4]));
+
+        // Or from the given item
+        delete_items(&repo, &IDS);
+        delete_checkpoint(&repo, FOLLOW_ITEMS_CHECKPOINT);
+        follow_new_and_updated_items(repo.clone(), 10, 2, Some(IDS[1]))
+            .await
+            .unwrap();
+        assert!(find_missing_items(&repo).await.is_empty());
+
+        // The later runs start after the checkpoint, whatever the start item
+        delete_items(&repo, &IDS);
+        repo.run(|repo| repo.upsert_checkpoint(FOLLOW_ITEMS_CHECKPOINT, IDS[2]))
+            .await
+            .unwrap();
+        follow_new_and_updated_items(repo.clone(), 10, 2, Some(IDS[1]))
+            .await
+            .unwrap();
+        assert_eq!(find_missing_items(&repo).await, IDS[1..3]);
+
+        // The checkpoint stops short of a failed item, which is collected by the next round
+        delete_items(&repo, &IDS);
+        repo.run(|repo| repo.upsert_checkpoint(FOLLOW_ITEMS_CHECKPOINT, IDS[0]))
+            .await
+            .unwrap();
+        STAND_IN_FAILING_ID.store(IDS[2], Ordering::SeqCst);
+        follow_new_and_updated_items(repo.clone(), 10, 2, None).await.unwrap();
+        STAND_IN_FAILING_ID.store(0, Ordering::SeqCst);
+        assert_eq!(find_missing_items(&repo).await, IDS[2..]);
+        let checkpoint = repo
+            .run(|repo| repo.find_checkpoint(FOLLOW_ITEMS_CHECKPOINT))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint, Some(IDS[1]));
+        follow_new_and_updated_items(repo.clone(), 10, 2, None).await.unwrap();
+        assert!(find_missing_items(&repo).await.is_empty());
+        let checkpoint = repo
+            .run(|repo| repo.find_checkpoint(FOLLOW_ITEMS_CHECKPOINT))
+            .await
+            .unwrap();
+        assert_eq!(checkpoint, Some(IDS[4]));
+    }
+
+    fn summaries(len: usize, num: usize) -> Vec<Summary> {
+        (0..num)
+            .map(|i| Summary {
+                topics: vec![],
+                summary: i.to_string().repeat(len),
+            })
+            .collect()
+    }
+
+    fn group_lens(groups: &[Vec<Summary>]) -> Vec<usize> {
+        groups.iter().map(|g| g.len()).collect()
+    }
+
+    #[test]
+    fn groups_summaries_under_budget_together() {
+        assert_eq!(group_lens(&group_summaries(summaries(10, 3), 100)), [3]);
+        assert!(group_summaries(vec![], 100).is_empty());
+    }
+
+    #[test]
+    fn groups_at_least_two_summaries_each() {
+        assert_eq!(group_lens(&group_summaries(summaries(10, 4), 20)), [2, 2]);
+        // Even when a pair is over the budget, so that every round reduces the number of summaries
+        assert_eq!(group_lens(&group_summaries(summaries(30, 4), 20)), [2, 2]);
+    }
+
+    #[test]
+    fn merges_last_summary_left_alone_into_previous_group() {
+        let groups = group_summaries(summaries(10, 5), 20);
+        assert_eq!(group_lens(&groups), [2, 3]);
+        assert_eq!(groups[1][2].summary, "4".repeat(10));
+    }
+}
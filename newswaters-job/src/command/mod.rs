@@ -1,15 +1,105 @@
-use std::env;
+use std::{future::Future, path::PathBuf, time::Duration};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::{builder::FalseyValueParser, Args};
+
+use crate::repository::{blob::BlobStore, Repository};
 
 pub(crate) mod analysis;
+pub(crate) mod blob;
 pub(crate) mod item;
+pub(crate) mod pipeline;
+pub(crate) mod story_list;
+pub(crate) mod task;
+pub(crate) mod user;
+
+#[derive(Args)]
+pub(crate) struct RepositoryArgs {
+    /// Keep blobs in this directory instead of the database
+    #[arg(long, env = "JOB_BLOB_STORE_DIR", global = true)]
+    blob_store_dir: Option<PathBuf>,
+    /// Write blobs to the database again, while still reading the directory of `--blob-store-dir`
+    #[arg(long, env = "JOB_BLOB_STORE_DATABASE", global = true, value_parser = FalseyValueParser::new())]
+    blob_store_database: bool,
+    /// The number of connections to the database
+    #[arg(long, env = "DATABASE_POOL_SIZE", global = true, default_value_t = 10)]
+    database_pool_size: u32,
+}
+
+pub(crate) fn new_repository(args: RepositoryArgs) -> Result<Repository> {
+    let blob_store = match (args.blob_store_dir, args.blob_store_database) {
+        (Some(dir), false) => BlobStore::Filesystem(dir),
+        (dir, _) => BlobStore::Database(dir),
+    };
+    Repository::new(blob_store, args.database_pool_size)
+}
 
-fn shorten_text(text: &str) -> Result<String> {
-    let min_line_len: usize = env::var("JOB_TEXT_MIN_LINE_LEN").unwrap_or("80".to_string()).parse()?;
-    let max_total_len: usize = env::var("JOB_TEXT_MAX_TOTAL_LEN")
-        .unwrap_or("4800".to_string())
-        .parse()?;
+async fn retry<T, F, Fut>(f: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let max_retry_count = 100;
+    let mut retry_count = 0;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                retry_count += 1;
+                if retry_count >= max_retry_count {
+                    bail!(e)
+                }
+                continue;
+            }
+        }
+    }
+}
+
+// Splits the text at line breaks into chunks of up to `max_len` bytes, where longer lines are split at whitespaces
+fn split_text(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    for line in text.split('\n').map(str::trim).filter(|l| !l.is_empty()) {
+        for part in split_line(line, max_len) {
+            if !chunk.is_empty() && chunk.len() + 1 + part.len() > max_len {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            if !chunk.is_empty() {
+                chunk.push('\n');
+            }
+            chunk.push_str(part);
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+fn split_line(line: &str, max_len: usize) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut rest = line;
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // A line without whitespaces, e.g. a long URL, is split anywhere
+        let end = match rest[..end].rfind(char::is_whitespace) {
+            Some(index) if index > 0 => index,
+            _ => end,
+        };
+        parts.push(rest[..end].trim_end());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+fn shorten_text(text: &str, min_line_len: usize, max_total_len: usize) -> String {
     let mut lines = vec![];
     let mut total_len = 0;
     for line in text
@@ -27,5 +117,36 @@ fn shorten_text(text: &str) -> Result<String> {
             total_len += length;
         }
     }
-    return Ok(lines.join("\n"));
+    return lines.join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_text_under_budget_into_one_chunk() {
+        assert_eq!(
+            split_text("First line\n\n  Second line  \n", 100),
+            ["First line\nSecond line"]
+        );
+        assert!(split_text("\n \n", 100).is_empty());
+    }
+
+    #[test]
+    fn splits_text_at_line_breaks() {
+        assert_eq!(split_text("aaaa\nbbbb\ncccc", 9), ["aaaa\nbbbb", "cccc"]);
+    }
+
+    #[test]
+    fn splits_overlong_line_at_whitespaces() {
+        assert_eq!(split_line("one two three four", 9), ["one two", "three", "four"]);
+        assert_eq!(split_text("one two three four", 9), ["one two", "three", "four"]);
+    }
+
+    #[test]
+    fn splits_overlong_line_without_whitespaces_at_char_boundaries() {
+        assert_eq!(split_line("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(split_line("ééé", 3), ["é", "é", "é"]);
+    }
 }
@@ -0,0 +1,276 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{extract::State, routing, Json, Router};
+use chrono::{DateTime, Local};
+use clap::{Args, Command, FromArgMatches};
+use serde::Serialize;
+use tokio::{
+    self,
+    signal::unix::{signal, SignalKind},
+    sync::{watch, Mutex},
+    time::Instant,
+};
+
+use crate::{command, repository::Repository};
+
+// Any fixed number works, as long as nothing else takes an advisory lock with it
+const PIPELINE_LOCK_KEY: i64 = 0x6e657773;
+
+#[derive(Args)]
+pub(crate) struct RunPipelineArgs {
+    /// The interval between rounds of collecting new and updated items
+    #[arg(long, env = "JOB_PIPELINE_COLLECT_INTERVAL_SECS", default_value_t = 60)]
+    collect_interval_secs: u64,
+    /// The interval between rounds of fetching and retrying item URLs
+    #[arg(long, env = "JOB_PIPELINE_FETCH_INTERVAL_SECS", default_value_t = 300)]
+    fetch_interval_secs: u64,
+    /// The interval between rounds of summarizing texts
+    #[arg(long, env = "JOB_PIPELINE_SUMMARIZE_INTERVAL_SECS", default_value_t = 600)]
+    summarize_interval_secs: u64,
+    /// The interval between rounds of analyzing texts and summaries
+    #[arg(long, env = "JOB_PIPELINE_ANALYZE_INTERVAL_SECS", default_value_t = 600)]
+    analyze_interval_secs: u64,
+    /// The interval between rounds of embedding summaries and keywords
+    #[arg(long, env = "JOB_PIPELINE_EMBED_INTERVAL_SECS", default_value_t = 600)]
+    embed_interval_secs: u64,
+    /// Serves the status of the stages at `/status`
+    #[arg(long, env = "JOB_PIPELINE_STATUS_PORT", default_value_t = 3001)]
+    status_port: u16,
+    /// How long the running stage may take to finish after SIGTERM before it's cancelled
+    #[arg(long, env = "JOB_PIPELINE_SHUTDOWN_TIMEOUT_SECS", default_value_t = 60)]
+    shutdown_timeout_secs: u64,
+}
+
+impl RunPipelineArgs {
+    fn interval(&self, stage: Stage) -> Duration {
+        Duration::from_secs(match stage {
+            Stage::Collect => self.collect_interval_secs,
+            Stage::Fetch => self.fetch_interval_secs,
+            Stage::Summarize => self.summarize_interval_secs,
+            Stage::Analyze => self.analyze_interval_secs,
+            Stage::Embed => self.embed_interval_secs,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Stage {
+    Collect,
+    Fetch,
+    Summarize,
+    Analyze,
+    Embed,
+}
+
+// In the order of their dependencies, i.e. each stage works on what the previous ones have made
+const STAGES: [Stage; 5] = [
+    Stage::Collect,
+    Stage::Fetch,
+    Stage::Summarize,
+    Stage::Analyze,
+    Stage::Embed,
+];
+
+impl Stage {
+    fn name(&self) -> &str {
+        match self {
+            Stage::Collect => "collect",
+            Stage::Fetch => "fetch",
+            Stage::Summarize => "summarize",
+            Stage::Analyze => "analyze",
+            Stage::Embed => "embed",
+        }
+    }
+
+    // Every stage takes the options of its commands from the environment variables
+    async fn run(self, repo: Repository) -> Result<()> {
+        match self {
+            Stage::Collect => command::item::follow_items_once(repo, args_from_env()?).await,
+            Stage::Fetch => {
+                command::item::collect_item_urls(repo.clone(), args_from_env()?).await?;
+                command::item::retry_item_urls(repo, args_from_env()?).await
+            }
+            Stage::Summarize => command::item::summarize_texts(repo, args_from_env()?).await,
+            Stage::Analyze => {
+                command::analysis::analyze_story_texts(repo.clone(), args_from_env()?).await?;
+                command::analysis::analyze_comment_texts(repo.clone(), args_from_env()?).await?;
+                command::analysis::analyze_summaries(repo, args_from_env()?).await
+            }
+            Stage::Embed => {
+                command::item::embed_summaries(repo.clone(), args_from_env()?).await?;
+                command::analysis::embed_keywords(repo, args_from_env()?).await
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PipelineStatus {
+    running: Option<Stage>,
+    stages: Vec<StageStatus>,
+}
+
+#[derive(Serialize)]
+struct StageStatus {
+    stage: Stage,
+    interval_secs: u64,
+    runs_num: i32,
+    failures_num: i32,
+    last_started_at: Option<DateTime<Local>>,
+    last_finished_at: Option<DateTime<Local>>,
+    last_duration_secs: Option<f64>,
+    last_error: Option<String>,
+}
+
+// Runs the stages one at a time in the order of their dependencies, each of them once its interval has passed.
+// Only a single pipeline runs against a database at a time.
+pub(crate) async fn run_pipeline(repo: Repository, args: RunPipelineArgs) -> Result<()> {
+    let lock = match repo.run(|repo| repo.try_advisory_lock(PIPELINE_LOCK_KEY)).await? {
+        Some(lock) => lock,
+        None => bail!("Another pipeline is already running against the database"),
+    };
+    let status = Arc::new(Mutex::new(PipelineStatus {
+        running: None,
+        stages: STAGES
+            .iter()
+            .map(|stage| StageStatus {
+                stage: *stage,
+                interval_secs: args.interval(*stage).as_secs(),
+                runs_num: 0,
+                failures_num: 0,
+                last_started_at: None,
+                last_finished_at: None,
+                last_duration_secs: None,
+                last_error: None,
+            })
+            .collect(),
+    }));
+    // Bound before the server is spawned, so that a port in use fails the startup rather than the shutdown
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], args.status_port)))
+        .with_context(|| format!("Failed to bind the status port {}", args.status_port))?;
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    tokio::spawn(wait_shutdown_signal(shutdown_sender));
+    let server = tokio::spawn(serve_status(listener, Arc::clone(&status), shutdown_receiver.clone()));
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_secs);
+    let mut next_run_ats = vec![Instant::now(); STAGES.len()];
+    let mut shutdown = shutdown_receiver.clone();
+    while !*shutdown.borrow() {
+        for (i, stage) in STAGES.iter().enumerate() {
+            if *shutdown.borrow() {
+                break;
+            }
+            if next_run_ats[i] > Instant::now() {
+                continue;
+            }
+            run_stage(&repo, i, *stage, &status, shutdown_receiver.clone(), shutdown_timeout).await;
+            next_run_ats[i] = Instant::now() + args.interval(*stage);
+        }
+        let wake_at = next_run_ats.iter().min().copied().unwrap_or(Instant::now());
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake_at) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+    server.await??;
+    tokio::task::spawn_blocking(move || lock.unlock()).await??;
+    println!("[INFO] main.run_pipeline: shut down");
+    Ok(())
+}
+
+async fn run_stage(
+    repo: &Repository,
+    i: usize,
+    stage: Stage,
+    status: &Mutex<PipelineStatus>,
+    shutdown: watch::Receiver<bool>,
+    shutdown_timeout: Duration,
+) {
+    {
+        let mut status = status.lock().await;
+        status.running = Some(stage);
+        status.stages[i].last_started_at = Some(Local::now());
+    }
+    let start_time = Instant::now();
+    let result = tokio::select! {
+        result = stage.run(repo.clone()) => result,
+        _ = wait_cancellation(shutdown, shutdown_timeout) => Err(anyhow!("Cancelled on shutdown")),
+    };
+    let duration = start_time.elapsed();
+    match &result {
+        Ok(_) => println!(
+            "[INFO] main.run_pipeline (stage={}): duration_secs={:.1}",
+            stage.name(),
+            duration.as_secs_f64()
+        ),
+        Err(e) => println!("[ERR] main.run_pipeline (stage={}): err={}", stage.name(), e),
+    };
+    let mut status = status.lock().await;
+    status.running = None;
+    let stage_status = &mut status.stages[i];
+    stage_status.runs_num += 1;
+    stage_status.last_finished_at = Some(Local::now());
+    stage_status.last_duration_secs = Some(duration.as_secs_f64());
+    stage_status.last_error = match result {
+        Ok(_) => None,
+        Err(e) => {
+            stage_status.failures_num += 1;
+            Some(e.to_string())
+        }
+    };
+}
+
+// Resolves once the shutdown has been waiting for the running stage longer than the timeout
+async fn wait_cancellation(mut shutdown: watch::Receiver<bool>, timeout: Duration) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            // Nothing can ask for a shutdown anymore
+            std::future::pending::<()>().await;
+        }
+    }
+    tokio::time::sleep(timeout).await;
+}
+
+async fn wait_shutdown_signal(shutdown: watch::Sender<bool>) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    println!("[INFO] main.run_pipeline: shutting down after the running stage");
+    shutdown.send(true)?;
+    Ok(())
+}
+
+async fn serve_status(
+    listener: TcpListener,
+    status: Arc<Mutex<PipelineStatus>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", routing::get(|| async { "Ok" }))
+        .route("/status", routing::get(get_status))
+        .with_state(status);
+    axum::Server::from_tcp(listener)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+        })
+        .await?;
+    Ok(())
+}
+
+async fn get_status(State(status): State<Arc<Mutex<PipelineStatus>>>) -> Json<serde_json::Value> {
+    Json(serde_json::to_value(&*status.lock().await).unwrap_or_default())
+}
+
+// Parses the options of a command as if it were run without any arguments
+fn args_from_env<T: Args + FromArgMatches>() -> Result<T> {
+    let matches = T::augment_args(Command::new("run-pipeline")).try_get_matches_from(["run-pipeline"])?;
+    Ok(T::from_arg_matches(&matches)?)
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use chrono::Local;
+
+use crate::{
+    repository::Repository,
+    service::{hacker_news, StoryList},
+};
+
+pub(crate) async fn snapshot_lists(repo: Repository) -> Result<()> {
+    // All lists of a snapshot share the same timestamp
+    let created_at = Local::now();
+    for list in StoryList::ALL {
+        let item_ids = match hacker_news::get_story_ids(list).await {
+            Ok(item_ids) => item_ids,
+            Err(e) => {
+                println!("[ERR] hacker_news.get_story_ids (list={}): err={e}", list.name());
+                continue;
+            }
+        };
+        println!(
+            "[INFO] main.snapshot_lists (list={}): item_ids.len={}",
+            list.name(),
+            item_ids.len()
+        );
+        repo.run(move |repo| repo.insert_story_list(list, item_ids, created_at))
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::{env, time::Duration};
+
+use anyhow::Result;
+use chrono::Local;
+use clap::{builder::FalseyValueParser, Args};
+use tokio::{self, task::JoinHandle};
+
+use crate::{
+    repository::Repository,
+    service::{Task, TaskKind},
+};
+
+#[derive(Args, Clone)]
+pub(crate) struct QueueArgs {
+    /// Only work on the tasks queued by others, e.g. to share their load from another machine
+    #[arg(long, env = "JOB_QUEUE_NO_ENQUEUE", value_parser = FalseyValueParser::new())]
+    pub(crate) no_enqueue: bool,
+    /// Failed tasks are dead-lettered after this number of attempts
+    #[arg(long, env = "JOB_QUEUE_MAX_ATTEMPTS_NUM", default_value_t = 5)]
+    task_max_attempts_num: i32,
+    /// The delay before retrying a failed task, which doubles after each attempt
+    #[arg(long, env = "JOB_QUEUE_RETRY_BASE_SECS", default_value_t = 60)]
+    task_retry_base_secs: i64,
+    /// Running tasks whose worker hasn't sent a heartbeat for this long are taken over by others
+    #[arg(long, env = "JOB_QUEUE_HEARTBEAT_TIMEOUT_SECS", default_value_t = 300)]
+    heartbeat_timeout_secs: i64,
+}
+
+// Claims tasks of a kind from the queue shared by every job process, and keeps them alive with heartbeats
+// while they're worked on.
+pub(crate) struct Worker {
+    kind: TaskKind,
+    name: String,
+    args: QueueArgs,
+    heartbeat: JoinHandle<()>,
+}
+
+impl Worker {
+    pub(crate) fn start(repo: &Repository, kind: TaskKind, args: &QueueArgs) -> Self {
+        let name = format!(
+            "{}-{}-{}",
+            env::var("HOSTNAME").unwrap_or("localhost".to_string()),
+            std::process::id(),
+            rand::random::<u32>()
+        );
+        let heartbeat_name = name.clone();
+        let interval = Duration::from_secs(std::cmp::max(args.heartbeat_timeout_secs / 3, 1) as u64);
+        let repo = repo.clone();
+        let heartbeat = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let worker = heartbeat_name.clone();
+                match repo.run(move |repo| repo.heartbeat_tasks(&worker)).await {
+                    Ok(_) => {}
+                    Err(e) => println!("[ERR] repo.heartbeat_tasks (worker={heartbeat_name}): err={e}"),
+                };
+            }
+        });
+        Self {
+            kind,
+            name,
+            args: args.clone(),
+            heartbeat,
+        }
+    }
+
+    pub(crate) async fn enqueue(&self, repo: &Repository, item_ids: Vec<i32>) -> Result<()> {
+        let kind = self.kind;
+        let item_ids_len = item_ids.len();
+        let enqueued_num = repo.run(move |repo| repo.enqueue_tasks(kind, &item_ids)).await?;
+        println!(
+            "[INFO] main.worker.enqueue (kind={}): item_ids.len={}, enqueued_num={}",
+            self.kind.name(),
+            item_ids_len,
+            enqueued_num
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn claim(&self, repo: &Repository, limit: usize) -> Result<Vec<Task>> {
+        let (kind, name, max_attempts_num) = (self.kind, self.name.clone(), self.args.task_max_attempts_num);
+        let stale_before = Local::now() - chrono::Duration::seconds(self.args.heartbeat_timeout_secs);
+        repo.run(move |repo| repo.claim_tasks(kind, &name, stale_before, max_attempts_num, limit))
+            .await
+    }
+
+    // A task taken over by another worker meanwhile is left to it, whether it has failed or not
+    pub(crate) async fn finish(&self, repo: &Repository, task: &Task, result: Result<()>) -> Result<()> {
+        let (id, name) = (task.id, self.name.clone());
+        let held = match result {
+            Ok(_) => repo.run(move |repo| repo.finish_task(id, &name)).await?,
+            Err(e) => {
+                println!(
+                    "[ERR] main.worker.finish (kind={}, id={}): attempts_num={}, err={}",
+                    self.kind.name(),
+                    task.item_id,
+                    task.attempts_num,
+                    e
+                );
+                let delay_secs = retry_delay_secs(self.args.task_retry_base_secs, task.attempts_num);
+                let run_at = Local::now() + chrono::Duration::seconds(delay_secs);
+                let max_attempts_num = self.args.task_max_attempts_num;
+                repo.run(move |repo| repo.fail_task(id, &name, max_attempts_num, run_at, &e.to_string()))
+                    .await?
+            }
+        };
+        if !held {
+            println!(
+                "[ERR] main.worker.finish (kind={}, id={}): The task has been taken over by another worker",
+                self.kind.name(),
+                task.item_id
+            );
+        }
+        Ok(())
+    }
+}
+
+// A failed task is retried with exponential backoff, i.e. the waiting time doubles after each failed attempt
+fn retry_delay_secs(retry_base_secs: i64, attempts_num: i32) -> i64 {
+    retry_base_secs.saturating_mul(1 << std::cmp::min(attempts_num - 1, 20))
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+    }
+}
+
+#[derive(Args)]
+pub(crate) struct RequeueDeadTasksArgs {
+    /// Only requeue the tasks of this kind
+    #[arg(long, value_enum)]
+    kind: Option<TaskKind>,
+}
+
+pub(crate) async fn requeue_dead_tasks(repo: Repository, args: RequeueDeadTasksArgs) -> Result<()> {
+    let requeued_num = repo.run(move |repo| repo.requeue_dead_tasks(args.kind)).await?;
+    println!("[INFO] main.requeue_dead_tasks: requeued_num={}", requeued_num);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_retry_delay_after_each_attempt() {
+        assert_eq!(retry_delay_secs(60, 1), 60);
+        assert_eq!(retry_delay_secs(60, 2), 120);
+        assert_eq!(retry_delay_secs(60, 5), 960);
+        // The delay stops growing at some point rather than overflowing
+        assert_eq!(retry_delay_secs(60, 100), retry_delay_secs(60, 21));
+        assert_eq!(retry_delay_secs(i64::MAX, 2), i64::MAX);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use chrono::{Duration, Local};
+use clap::Args;
+use tokio::{self, sync::Semaphore};
+
+use crate::service::hacker_news;
+use crate::{command, repository::Repository};
+
+#[derive(Args)]
+pub(crate) struct CollectUsersArgs {
+    /// The number of users to collect, the least recently updated first
+    #[arg(long, env = "JOB_COLLECT_USERS_NUM", default_value_t = 10000)]
+    users_num: usize,
+    /// Users updated within this period are skipped
+    #[arg(long, env = "JOB_USERS_STALE_SECS", default_value_t = 604800)]
+    stale_secs: i64,
+    #[arg(long, env = "JOB_PERMITS_NUM", default_value_t = 100)]
+    permits_num: usize,
+    #[arg(long, env = "JOB_CHUNK_SIZE", default_value_t = 1000)]
+    chunk_size: usize,
+}
+
+pub(crate) async fn collect_users(repo: Repository, args: CollectUsersArgs) -> Result<()> {
+    let stale_before = Local::now() - Duration::seconds(args.stale_secs);
+    let users_num = args.users_num;
+    let user_ids = repo
+        .run(move |repo| repo.find_stale_users(stale_before, users_num))
+        .await?;
+    for chunk in user_ids.chunks(args.chunk_size) {
+        collect_chunk_users(repo.clone(), args.permits_num, chunk.to_vec()).await?;
+        println!("[INFO] main.collect_users: chunk.len={}", chunk.len());
+    }
+    Ok(())
+}
+
+pub(crate) async fn collect_chunk_users(repo: Repository, permits_num: usize, user_ids: Vec<String>) -> Result<()> {
+    let semaphore = Arc::new(Semaphore::new(permits_num));
+    let mut handles = HashMap::new();
+    for id in user_ids {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let repo_inst = repo.clone();
+        let user_id = id.clone();
+        let handle = tokio::spawn(async move {
+            match command::retry(|| hacker_news::get_user(&user_id)).await {
+                Ok(Some(user)) => match repo_inst.run(move |repo| repo.upsert_user(user)).await {
+                    Ok(_) => {}
+                    Err(e) => println!("[ERR] repo.upsert_user (id={user_id}): err={e}"),
+                },
+                // Accounts which have been deleted are returned as `null`
+                Ok(None) => {
+                    let id = user_id.clone();
+                    match repo_inst.run(move |repo| repo.upsert_deleted_user(id)).await {
+                        Ok(_) => {}
+                        Err(e) => println!("[ERR] repo.upsert_deleted_user (id={user_id}): err={e}"),
+                    }
+                }
+                Err(e) => bail!(e),
+            };
+            drop(permit);
+            Ok(())
+        });
+        handles.insert(id, handle);
+    }
+    for (id, handle) in handles {
+        match handle.await? {
+            Ok(_) => {}
+            Err(e) => println!("[ERR] main.collect_chunk_users.handle (id={id}): err={e}"),
+        };
+    }
+    Ok(())
+}
@@ -3,32 +3,117 @@ mod repository;
 mod schema;
 mod service;
 
-use std::{env, sync::Arc};
-
 use anyhow::Result;
-use tokio::{self, sync::Mutex};
+use clap::{Parser, Subcommand};
+
+use crate::command::{
+    analysis::{AnalyzeCommentTextsArgs, AnalyzeStoryTextsArgs, AnalyzeSummariesArgs, EmbedKeywordsArgs},
+    blob::CompressBlobsArgs,
+    item::{
+        CollectItemUrlsArgs, CollectItemsArgs, EmbedSummariesArgs, FollowItemsArgs, ReextractItemUrlsArgs,
+        RefreshItemsArgs, RetryItemUrlsArgs, SummarizeTextsArgs,
+    },
+    pipeline::RunPipelineArgs,
+    task::RequeueDeadTasksArgs,
+    user::CollectUsersArgs,
+    RepositoryArgs,
+};
 
-use crate::repository::Repository;
+// Every option can also be given by the environment variable shown in `--help`,
+// and the command line takes precedence over it.
+#[derive(Parser)]
+#[command(version, about = "Collects, summarizes and analyzes Hacker News stories")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    repository: RepositoryArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // Collecting
+    /// Collect the latest items
+    CollectItems(CollectItemsArgs),
+    /// Keep collecting new and updated items
+    FollowItems(FollowItemsArgs),
+    /// Collect the recent items again to update their scores and comments
+    RefreshItems(RefreshItemsArgs),
+    /// Fetch the pages linked from stories
+    CollectItemUrls(CollectItemUrlsArgs),
+    /// Fetch the pages which have failed transiently again
+    RetryItemUrls(RetryItemUrlsArgs),
+    /// Collect the users who haven't been updated for a while
+    CollectUsers(CollectUsersArgs),
+    /// Take a snapshot of the top, new and best stories
+    SnapshotLists,
+    // Summarize and analyze
+    /// Summarize the texts of the top stories
+    SummarizeTexts(SummarizeTextsArgs),
+    /// Extract keywords from the texts of the top stories
+    AnalyzeStoryTexts(AnalyzeStoryTextsArgs),
+    /// Extract passages from the latest comments
+    AnalyzeCommentTexts(AnalyzeCommentTextsArgs),
+    /// Extract passages from the summaries of the top stories
+    AnalyzeSummaries(AnalyzeSummariesArgs),
+    // Embedding
+    /// Embed the summaries into the search engine
+    EmbedSummaries(EmbedSummariesArgs),
+    /// Embed the keywords into the search engine
+    EmbedKeywords(EmbedKeywordsArgs),
+    // Maintenance
+    /// Compress the uncompressed blobs, or move them to the filesystem
+    CompressBlobs(CompressBlobsArgs),
+    /// Extract the texts from the stored HTML again
+    ReextractItemUrls(ReextractItemUrlsArgs),
+    /// Give the tasks which have run out of attempts another chance
+    RequeueDeadTasks(RequeueDeadTasksArgs),
+    // Daemon
+    /// Keep running the stages from collecting to embedding, each on its own schedule
+    RunPipeline(RunPipelineArgs),
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let repo = Repository::new()?;
-    if args.len() >= 2 {
-        match args[1].as_str() {
-            // Collecting
-            "collect-items" => command::item::collect_items(Arc::new(Mutex::new(repo))).await?,
-            "collect-item-urls" => command::item::collect_item_urls(Arc::new(Mutex::new(repo))).await?,
-            // Summarize and analyze
-            "summarize-texts" => command::item::summarize_texts(repo).await?,
-            "analyze-story-texts" => command::analysis::analyze_story_texts(repo).await?,
-            "analyze-comment-texts" => command::analysis::analyze_comment_texts(repo).await?,
-            "analyze-summaries" => command::analysis::analyze_summaries(repo).await?,
-            // Embedding
-            "embed-summaries" => command::item::embed_summaries(repo).await?,
-            "embed-keywords" => command::analysis::embed_keywords(repo).await?,
-            _ => {}
-        }
+    // Parsed before connecting to the database, so that `--help` and mistyped commands don't need one
+    let cli = Cli::parse();
+    let repo = command::new_repository(cli.repository)?;
+    match cli.command {
+        // Collecting
+        Command::CollectItems(args) => command::item::collect_items(repo, args).await?,
+        Command::FollowItems(args) => command::item::follow_items(repo, args).await?,
+        Command::RefreshItems(args) => command::item::refresh_items(repo, args).await?,
+        Command::CollectItemUrls(args) => command::item::collect_item_urls(repo, args).await?,
+        Command::RetryItemUrls(args) => command::item::retry_item_urls(repo, args).await?,
+        Command::CollectUsers(args) => command::user::collect_users(repo, args).await?,
+        Command::SnapshotLists => command::story_list::snapshot_lists(repo).await?,
+        // Summarize and analyze
+        Command::SummarizeTexts(args) => command::item::summarize_texts(repo, args).await?,
+        Command::AnalyzeStoryTexts(args) => command::analysis::analyze_story_texts(repo, args).await?,
+        Command::AnalyzeCommentTexts(args) => command::analysis::analyze_comment_texts(repo, args).await?,
+        Command::AnalyzeSummaries(args) => command::analysis::analyze_summaries(repo, args).await?,
+        // Embedding
+        Command::EmbedSummaries(args) => command::item::embed_summaries(repo, args).await?,
+        Command::EmbedKeywords(args) => command::analysis::embed_keywords(repo, args).await?,
+        // Maintenance
+        Command::CompressBlobs(args) => command::blob::compress_blobs(repo, args).await?,
+        Command::ReextractItemUrls(args) => command::item::reextract_item_urls(repo, args).await?,
+        Command::RequeueDeadTasks(args) => command::task::requeue_dead_tasks(repo, args).await?,
+        // Daemon
+        Command::RunPipeline(args) => command::pipeline::run_pipeline(repo, args).await?,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    // The flattened args of every subcommand must not clash with each other
+    #[test]
+    fn verifies_cli() {
+        Cli::command().debug_assert();
+    }
+}
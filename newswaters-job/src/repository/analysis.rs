@@ -1,8 +1,6 @@
-use std::env;
-
 use anyhow::Result;
 use chrono::{DateTime, Local};
-use diesel::{pg::Pg, prelude::*, sql_types::*};
+use diesel::{prelude::*, sql_types::*};
 
 use super::Repository;
 use crate::{schema::analyses, service::Analysis};
@@ -11,20 +9,24 @@ impl Repository {
     //////////////////////
     // Analyze story texts
     //////////////////////
+    // With the current `prompts`, the analyses generated by the older ones are also taken as missing
     pub(crate) fn find_keyword_missing_analyses(
-        &mut self,
+        &self,
         ids: &[i32],
+        prompts: Option<&[String]>,
     ) -> Result<Vec<(i32, String, Option<String>, Option<String>)>> {
-        let keyword_missing_analyses = diesel::sql_query(format!(
-            "SELECT id, title, items.text, item_urls.text AS url_text \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+        let keyword_missing_analyses = diesel::sql_query(
+            "SELECT id, items.title, items.text, item_urls.text AS url_text \
+            FROM unnest($1) AS s(i) \
             JOIN items ON s.i = items.id \
             JOIN item_urls ON s.i = item_urls.item_id \
             LEFT JOIN analyses ON s.i = analyses.item_id \
-            WHERE title IS NOT NULL AND (items.text IS NOT NULL OR item_urls.text IS NOT NULL) AND keyword IS NULL",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
-        .get_results::<KeywordMissingAnalysisRecord>(&mut self.connection)?
+            WHERE items.title IS NOT NULL AND (items.text IS NOT NULL OR item_urls.text IS NOT NULL) \
+            AND (keyword IS NULL OR ($2 IS NOT NULL AND NOT COALESCE(keyword_prompts <@ $2, false)))",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<KeywordMissingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.title, r.text, r.url_text))
         .collect();
@@ -32,57 +34,58 @@ impl Repository {
     }
 
     pub(crate) fn find_keyword_missing_analyses_excluding(
-        &mut self,
+        &self,
         ids: &[i32],
         limit: usize,
+        prompts: Option<&[String]>,
+        follows_summaries: bool,
     ) -> Result<Vec<(i32, String, Option<String>, Option<String>)>> {
-        let item_url_condition = if env::var("JOB_FIND_ANALYSES_FOLLOW_SUMMARIES").is_ok() {
-            "(item_urls.text IS NOT NULL AND item_urls.summary IS NOT NULL)"
-        } else {
-            "item_urls.text IS NOT NULL"
-        };
-        let keyword_missing_analyses = diesel::sql_query(format!(
-            "SELECT id, title, items.text, item_urls.text AS url_text \
+        let keyword_missing_analyses = diesel::sql_query(
+            "SELECT id, items.title, items.text, item_urls.text AS url_text \
             FROM items \
             JOIN item_urls ON items.id = item_urls.item_id \
             LEFT JOIN analyses ON items.id = analyses.item_id \
-            WHERE title IS NOT NULL AND (items.text IS NOT NULL OR {}) AND keyword IS NULL AND id NOT IN ({}) \
-            ORDER BY id DESC LIMIT {}",
-            item_url_condition,
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", "),
-            limit
-        ))
-        .get_results::<KeywordMissingAnalysisRecord>(&mut self.connection)?
+            WHERE items.title IS NOT NULL \
+            AND (items.text IS NOT NULL OR (item_urls.text IS NOT NULL AND (NOT $1 OR item_urls.summary IS NOT NULL))) \
+            AND (keyword IS NULL OR ($4 IS NOT NULL AND NOT COALESCE(keyword_prompts <@ $4, false))) \
+            AND id <> ALL($2) \
+            ORDER BY id DESC LIMIT $3",
+        )
+        .bind::<Bool, _>(follows_summaries)
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<BigInt, _>(limit as i64)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<KeywordMissingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.title, r.text, r.url_text))
         .collect();
         return Ok(keyword_missing_analyses);
     }
 
-    pub(crate) fn find_keyword_existing_analyses(&mut self, limit: usize) -> Result<Vec<i32>> {
-        let keyword_existing_analyses = diesel::sql_query(format!(
+    pub(crate) fn find_keyword_existing_analyses(&self, limit: usize) -> Result<Vec<i32>> {
+        let keyword_existing_analyses = diesel::sql_query(
             "SELECT item_id \
             FROM analyses \
             WHERE keyword IS NOT NULL \
-            ORDER BY item_id DESC LIMIT {}",
-            limit
-        ))
-        .get_results::<KeywordExistingAnalysisRecord>(&mut self.connection)?
+            ORDER BY item_id DESC LIMIT $1",
+        )
+        .bind::<BigInt, _>(limit as i64)
+        .get_results::<KeywordExistingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.item_id))
         .collect();
         return Ok(keyword_existing_analyses);
     }
 
-    pub(crate) fn find_analysis_keywords(&mut self, ids: &[i32]) -> Result<Vec<(i32, String)>> {
-        let analysis_keywords = diesel::sql_query(format!(
+    pub(crate) fn find_analysis_keywords(&self, ids: &[i32]) -> Result<Vec<(i32, String)>> {
+        let analysis_keywords = diesel::sql_query(
             "SELECT item_id, keyword \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+            FROM unnest($1) AS s(i) \
             JOIN analyses ON s.i = analyses.item_id \
             WHERE keyword IS NOT NULL",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
-        .get_results::<AnalysisKeywordRecord>(&mut self.connection)?
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .get_results::<AnalysisKeywordRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.item_id, r.keyword))
         .collect();
@@ -93,39 +96,70 @@ impl Repository {
     // Analyze comment texts
     ////////////////////////
     pub(crate) fn find_text_passage_missing_analyses(
-        &mut self,
+        &self,
         min_len: usize,
         limit: usize,
+        prompts: Option<&[String]>,
     ) -> Result<Vec<(i32, String)>> {
-        let text_passage_missing_analyses = diesel::sql_query(format!(
+        let text_passage_missing_analyses = diesel::sql_query(
             "SELECT id, text \
             FROM items \
             LEFT JOIN analyses ON items.id = analyses.item_id \
-            WHERE type = 'comment' AND text IS NOT NULL AND length(text) >= {} \
-            AND text_passage IS NULL \
-            ORDER BY id DESC LIMIT {}",
-            min_len, limit
-        ))
-        .get_results::<TextPassageMissingAnalysisRecord>(&mut self.connection)?
+            WHERE type = 'comment' AND text IS NOT NULL AND length(text) >= $1 \
+            AND (text_passage IS NULL OR ($3 IS NOT NULL AND NOT COALESCE(text_passage_prompts <@ $3, false))) \
+            ORDER BY id DESC LIMIT $2",
+        )
+        .bind::<Integer, _>(min_len as i32)
+        .bind::<BigInt, _>(limit as i64)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<TextPassageMissingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.text))
         .collect();
         return Ok(text_passage_missing_analyses);
     }
 
+    pub(crate) fn find_text_passage_missing_comments(
+        &self,
+        ids: &[i32],
+        prompts: Option<&[String]>,
+    ) -> Result<Vec<(i32, String)>> {
+        let text_passage_missing_comments = diesel::sql_query(
+            "SELECT id, text \
+            FROM unnest($1) AS s(i) \
+            JOIN items ON s.i = items.id \
+            LEFT JOIN analyses ON s.i = analyses.item_id \
+            WHERE type = 'comment' AND text IS NOT NULL \
+            AND (text_passage IS NULL OR ($2 IS NOT NULL AND NOT COALESCE(text_passage_prompts <@ $2, false)))",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<TextPassageMissingAnalysisRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.text))
+        .collect();
+        return Ok(text_passage_missing_comments);
+    }
+
     ////////////////////
     // Analyze summaries
     ////////////////////
-    pub(crate) fn find_summary_passage_missing_analyses(&mut self, ids: &[i32]) -> Result<Vec<(i32, String)>> {
-        let summary_passage_missing_analyses = diesel::sql_query(format!(
+    pub(crate) fn find_summary_passage_missing_analyses(
+        &self,
+        ids: &[i32],
+        prompts: Option<&[String]>,
+    ) -> Result<Vec<(i32, String)>> {
+        let summary_passage_missing_analyses = diesel::sql_query(
             "SELECT s.i AS id, summary \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+            FROM unnest($1) AS s(i) \
             JOIN item_urls ON s.i = item_urls.item_id \
             LEFT JOIN analyses ON s.i = analyses.item_id \
-            WHERE summary IS NOT NULL AND summary_passage IS NULL",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
-        .get_results::<SummaryPassageMissingAnalysisRecord>(&mut self.connection)?
+            WHERE summary IS NOT NULL \
+            AND (summary_passage IS NULL OR ($2 IS NOT NULL AND NOT COALESCE(summary_passage_prompts <@ $2, false)))",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<SummaryPassageMissingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.summary))
         .collect();
@@ -133,20 +167,24 @@ impl Repository {
     }
 
     pub(crate) fn find_summary_passage_missing_analyses_excluding(
-        &mut self,
+        &self,
         ids: &[i32],
         limit: usize,
+        prompts: Option<&[String]>,
     ) -> Result<Vec<(i32, String)>> {
-        let summary_passage_missing_analyses = diesel::sql_query(format!(
+        let summary_passage_missing_analyses = diesel::sql_query(
             "SELECT item_urls.item_id AS id, summary \
             FROM item_urls \
             LEFT JOIN analyses ON item_urls.item_id = analyses.item_id \
-            WHERE summary IS NOT NULL AND summary_passage IS NULL AND item_urls.item_id NOT IN ({}) \
-            ORDER BY id DESC LIMIT {}",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", "),
-            limit
-        ))
-        .get_results::<SummaryPassageMissingAnalysisRecord>(&mut self.connection)?
+            WHERE summary IS NOT NULL \
+            AND (summary_passage IS NULL OR ($3 IS NOT NULL AND NOT COALESCE(summary_passage_prompts <@ $3, false))) \
+            AND item_urls.item_id <> ALL($1) \
+            ORDER BY id DESC LIMIT $2",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<BigInt, _>(limit as i64)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<SummaryPassageMissingAnalysisRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.summary))
         .collect();
@@ -156,19 +194,75 @@ impl Repository {
     /////////
     // Common
     /////////
-    pub(crate) fn insert_analysis(&mut self, analysis: Analysis) -> Result<()> {
-        let analysis_record = InsertAnalysisRecord {
-            item_id: analysis.item_id,
-            keyword: analysis.keyword,
-            text_passage: analysis.text_passage,
-            summary_passage: analysis.summary_passage,
-            created_at: Local::now(),
-            updated_at: Local::now(),
-        };
-        diesel::insert_into(analyses::table)
-            .values(&analysis_record)
-            .returning(InsertAnalysisRecord::as_returning())
-            .get_result(&mut self.connection)?;
+    // Only the generated fields are set, so that the other fields of an existing analysis are kept
+    pub(crate) fn insert_analysis(&self, analysis: Analysis) -> Result<()> {
+        let prompts_of = |field: &Option<String>| field.as_ref().map(|_| analysis.prompts.clone());
+        let (keyword_prompts, text_passage_prompts, summary_passage_prompts) = (
+            prompts_of(&analysis.keyword),
+            prompts_of(&analysis.text_passage),
+            prompts_of(&analysis.summary_passage),
+        );
+        diesel::sql_query(
+            "INSERT INTO analyses ( \
+                item_id, keyword, keyword_prompts, text_passage, text_passage_prompts, \
+                summary_passage, summary_passage_prompts, created_at, updated_at \
+            ) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8) \
+            ON CONFLICT (item_id) DO UPDATE SET \
+                keyword = COALESCE(excluded.keyword, analyses.keyword), \
+                keyword_prompts = COALESCE(excluded.keyword_prompts, analyses.keyword_prompts), \
+                text_passage = COALESCE(excluded.text_passage, analyses.text_passage), \
+                text_passage_prompts = COALESCE(excluded.text_passage_prompts, analyses.text_passage_prompts), \
+                summary_passage = COALESCE(excluded.summary_passage, analyses.summary_passage), \
+                summary_passage_prompts = COALESCE(excluded.summary_passage_prompts, analyses.summary_passage_prompts), \
+                updated_at = excluded.updated_at",
+        )
+        .bind::<Integer, _>(analysis.item_id)
+        .bind::<Nullable<Text>, _>(analysis.keyword)
+        .bind::<Nullable<Array<Text>>, _>(keyword_prompts)
+        .bind::<Nullable<Text>, _>(analysis.text_passage)
+        .bind::<Nullable<Array<Text>>, _>(text_passage_prompts)
+        .bind::<Nullable<Text>, _>(analysis.summary_passage)
+        .bind::<Nullable<Array<Text>>, _>(summary_passage_prompts)
+        .bind::<Timestamptz, _>(Local::now())
+        .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_analysis(&self, item_id: i32) -> Result<()> {
+        diesel::delete(analyses::table)
+            .filter(analyses::item_id.eq(item_id))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    // The summary passages follow the summaries, so they are generated again once the summaries have changed
+    pub(crate) fn invalidate_summary_passages(&self, item_ids: &[i32]) -> Result<()> {
+        diesel::sql_query(
+            "UPDATE analyses \
+            SET summary_passage = NULL, summary_passage_prompts = NULL, updated_at = $2 \
+            WHERE item_id = ANY($1)",
+        )
+        .bind::<Array<Integer>, _>(item_ids)
+        .bind::<Timestamptz, _>(Local::now())
+        .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    // Duplicate items share the analysis of their original, except for `text_passage` which is about their own text
+    pub(crate) fn copy_analysis(&self, item_id: i32, original_id: i32) -> Result<()> {
+        diesel::sql_query(
+            "INSERT INTO analyses ( \
+                item_id, keyword, keyword_prompts, summary_passage, summary_passage_prompts, created_at, updated_at \
+            ) \
+            SELECT $1, keyword, keyword_prompts, summary_passage, summary_passage_prompts, NOW(), NOW() \
+            FROM analyses \
+            WHERE item_id = $2 \
+            ON CONFLICT (item_id) DO NOTHING",
+        )
+        .bind::<Integer, _>(item_id)
+        .bind::<Integer, _>(original_id)
+        .execute(&mut self.pool.get()?)?;
         Ok(())
     }
 }
@@ -232,17 +326,46 @@ struct UpdateAnalysisRecord {
     updated_at: DateTime<Local>,
 }
 
-/////////
-// Common
-/////////
-#[derive(Queryable, Selectable, Insertable)]
-#[diesel(table_name = analyses)]
-#[diesel(check_for_backend(Pg))]
-struct InsertAnalysisRecord {
-    item_id: i32,
-    keyword: Option<String>,
-    text_passage: Option<String>,
-    summary_passage: Option<String>,
-    created_at: DateTime<Local>,
-    updated_at: DateTime<Local>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::tests::{connect, insert_stories};
+
+    #[test]
+    fn finds_keyword_missing_analyses_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_300_000..1_900_320_000).collect();
+        insert_stories(&repo, &ids);
+        assert!(repo.find_keyword_missing_analyses(&[], None).unwrap().is_empty());
+        assert_eq!(repo.find_keyword_missing_analyses(&ids, None).unwrap().len(), ids.len());
+    }
+
+    #[test]
+    fn finds_analysis_keywords_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_400_000..1_900_420_000).collect();
+        insert_stories(&repo, &ids);
+        diesel::sql_query("INSERT INTO analyses (item_id, keyword) SELECT i, 'keyword' FROM unnest($1) AS s(i)")
+            .bind::<Array<Integer>, _>(&ids)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        assert!(repo.find_analysis_keywords(&[]).unwrap().is_empty());
+        assert_eq!(repo.find_analysis_keywords(&ids).unwrap().len(), ids.len());
+    }
+
+    #[test]
+    fn finds_text_passage_missing_comments_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_500_000..1_900_520_000).collect();
+        insert_stories(&repo, &ids);
+        diesel::sql_query("UPDATE items SET type = 'comment', text = 'Comment' WHERE id = ANY($1)")
+            .bind::<Array<Integer>, _>(&ids)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        assert!(repo.find_text_passage_missing_comments(&[], None).unwrap().is_empty());
+        assert_eq!(
+            repo.find_text_passage_missing_comments(&ids, None).unwrap().len(),
+            ids.len()
+        );
+    }
 }
@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use diesel::{pg::Pg, prelude::*, upsert::excluded};
+use sha2::{Digest, Sha256};
+
+use super::Repository;
+use crate::schema::blobs;
+
+// Where blobs are written to, while they are read from either of them, falling back to the other one.
+// The database may be given the directory which blobs were written to before switching back to it.
+// Blobs are content-addressed, so the same content is only stored once.
+#[derive(Clone)]
+pub(crate) enum BlobStore {
+    Database(Option<PathBuf>),
+    Filesystem(PathBuf),
+}
+
+impl Repository {
+    pub(crate) fn insert_blob(&self, content: &[u8]) -> Result<String> {
+        let hash = format!("{:x}", Sha256::digest(content));
+        let compressed_content = zstd::encode_all(content, ZSTD_LEVEL)?;
+        match &self.blob_store {
+            BlobStore::Database(_) => {
+                let blob_record = InsertBlobRecord {
+                    hash: hash.clone(),
+                    content: compressed_content,
+                    compressed: true,
+                    created_at: Local::now(),
+                };
+                // Also replaces the content left uncompressed by the migration
+                diesel::insert_into(blobs::table)
+                    .values(&blob_record)
+                    .on_conflict(blobs::hash)
+                    .do_update()
+                    .set((
+                        blobs::content.eq(excluded(blobs::content)),
+                        blobs::compressed.eq(excluded(blobs::compressed)),
+                    ))
+                    .execute(&mut self.pool.get()?)?;
+            }
+            BlobStore::Filesystem(dir) => {
+                let path = blob_path(dir, &hash);
+                if !path.exists() {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    // Written atomically, so that a half-written file is never read,
+                    // where each writer has a file of its own, as replicas may write the same blob at the same time
+                    let tmp_path = path.with_extension(format!("{}.{}.tmp", std::process::id(), rand::random::<u64>()));
+                    fs::write(&tmp_path, compressed_content)?;
+                    fs::rename(&tmp_path, &path)?;
+                }
+                diesel::delete(blobs::table)
+                    .filter(blobs::hash.eq(&hash))
+                    .execute(&mut self.pool.get()?)?;
+            }
+        }
+        Ok(hash)
+    }
+
+    pub(crate) fn find_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let content = match &self.blob_store {
+            BlobStore::Filesystem(dir) => match find_file_blob(dir, hash)? {
+                Some(content) => Some(content),
+                None => self.find_database_blob(hash)?,
+            },
+            BlobStore::Database(dir) => match (self.find_database_blob(hash)?, dir) {
+                (Some(content), _) => Some(content),
+                (None, Some(dir)) => find_file_blob(dir, hash)?,
+                (None, None) => None,
+            },
+        };
+        let content = match content {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        if format!("{:x}", Sha256::digest(&content)) != hash {
+            bail!("Blob content doesn't match its hash: {}", hash);
+        }
+        return Ok(Some(content));
+    }
+
+    fn find_database_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let blob = blobs::table
+            .filter(blobs::hash.eq(hash))
+            .select(FindBlobRecord::as_select())
+            .first(&mut self.pool.get()?)
+            .optional()?;
+        let content = match blob {
+            Some(blob) if blob.compressed => Some(zstd::decode_all(blob.content.as_slice())?),
+            Some(blob) => Some(blob.content),
+            None => None,
+        };
+        return Ok(content);
+    }
+
+    // The blobs left in the database which are to be stored again, i.e. the uncompressed ones,
+    // and all of them when blobs are written to the filesystem, as they are moved there
+    pub(crate) fn find_pending_blobs(&self, limit: usize) -> Result<Vec<String>> {
+        let mut query = blobs::table.select(blobs::hash).into_boxed();
+        if let BlobStore::Database(_) = self.blob_store {
+            query = query.filter(blobs::compressed.eq(false));
+        }
+        let pending_blobs = query
+            .order(blobs::hash.asc())
+            .limit(limit as i64)
+            .load::<String>(&mut self.pool.get()?)?;
+        return Ok(pending_blobs);
+    }
+}
+
+fn find_file_blob(dir: &Path, hash: &str) -> Result<Option<Vec<u8>>> {
+    let path = blob_path(dir, hash);
+    if !path.exists() {
+        return Ok(None);
+    }
+    return Ok(Some(zstd::decode_all(fs::read(path)?.as_slice())?));
+}
+
+fn blob_path(dir: &Path, hash: &str) -> PathBuf {
+    // Spread files over subdirectories, as directories with too many files get slow
+    dir.join(&hash[..std::cmp::min(2, hash.len())])
+        .join(format!("{}.zst", hash))
+}
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Insertable)]
+#[diesel(table_name = blobs)]
+#[diesel(check_for_backend(Pg))]
+struct InsertBlobRecord {
+    hash: String,
+    content: Vec<u8>,
+    compressed: bool,
+    created_at: DateTime<Local>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = blobs)]
+#[diesel(check_for_backend(Pg))]
+struct FindBlobRecord {
+    content: Vec<u8>,
+    compressed: bool,
+}
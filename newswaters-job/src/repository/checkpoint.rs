@@ -0,0 +1,45 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use diesel::{prelude::*, upsert::excluded};
+
+use super::Repository;
+use crate::schema::checkpoints;
+
+impl Repository {
+    pub(crate) fn find_checkpoint(&self, name: &str) -> Result<Option<i32>> {
+        let checkpoint = checkpoints::table
+            .filter(checkpoints::name.eq(name))
+            .select(checkpoints::value)
+            .first::<i32>(&mut self.pool.get()?)
+            .optional()?;
+        return Ok(checkpoint);
+    }
+
+    pub(crate) fn upsert_checkpoint(&self, name: &str, value: i32) -> Result<()> {
+        let checkpoint_record = InsertCheckpointRecord {
+            name: name.to_string(),
+            value,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+        };
+        diesel::insert_into(checkpoints::table)
+            .values(&checkpoint_record)
+            .on_conflict(checkpoints::name)
+            .do_update()
+            .set((
+                checkpoints::value.eq(excluded(checkpoints::value)),
+                checkpoints::updated_at.eq(excluded(checkpoints::updated_at)),
+            ))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = checkpoints)]
+struct InsertCheckpointRecord {
+    name: String,
+    value: i32,
+    created_at: DateTime<Local>,
+    updated_at: DateTime<Local>,
+}
@@ -0,0 +1,48 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use diesel::prelude::*;
+
+use super::Repository;
+use crate::{schema::generations, service::Generation};
+
+impl Repository {
+    pub(crate) fn insert_generations(&self, item_id: i32, generations: Vec<Generation>) -> Result<()> {
+        if generations.is_empty() {
+            return Ok(());
+        }
+        let generation_records = generations
+            .into_iter()
+            .map(|g| InsertGenerationRecord {
+                item_id,
+                prompt: g.prompt,
+                input_hash: g.input_hash,
+                seed: Some(i64::from(g.seed)),
+                model: g.model,
+                temperature: g.temperature,
+                output: g.output,
+                error: g.error,
+                duration_ms: i32::try_from(g.duration.as_millis()).unwrap_or(i32::MAX),
+                created_at: Local::now(),
+            })
+            .collect::<Vec<InsertGenerationRecord>>();
+        diesel::insert_into(generations::table)
+            .values(&generation_records)
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = generations)]
+struct InsertGenerationRecord {
+    item_id: i32,
+    prompt: String,
+    input_hash: String,
+    seed: Option<i64>,
+    model: Option<String>,
+    temperature: Option<f32>,
+    output: Option<String>,
+    error: Option<String>,
+    duration_ms: i32,
+    created_at: DateTime<Local>,
+}
@@ -7,46 +7,57 @@ use diesel::{
     expression::AsExpression,
     pg::Pg,
     prelude::*,
+    r2d2::{ConnectionManager, Pool},
     serialize::{IsNull, ToSql},
     sql_types::*,
+    upsert::excluded,
 };
 
-use super::Repository;
+use super::{blob::BlobStore, Repository};
 use crate::{
-    schema::{item_urls, items, sql_types::ItemType},
-    service::{Item, ItemUrl},
+    schema::{
+        item_urls, items,
+        sql_types::{FetchStrategy, ItemType, ItemUrlStatus},
+    },
+    service::{self, ArticleMetadata, Item, ItemUrl, ItemUrlFailure, Summary},
 };
 
 impl Repository {
-    pub(crate) fn new() -> Result<Self> {
+    pub(crate) fn new(blob_store: BlobStore, pool_size: u32) -> Result<Self> {
+        Self::connect(&env::var("DATABASE_DB")?, blob_store, pool_size)
+    }
+
+    pub(crate) fn connect(database: &str, blob_store: BlobStore, pool_size: u32) -> Result<Self> {
         let database_url = format!(
             "postgres://{}:{}@{}:{}/{}",
             env::var("DATABASE_USER")?,
             env::var("DATABASE_PASSWORD")?,
             env::var("DATABASE_HOST")?,
             env::var("DATABASE_PORT").unwrap_or("5432".to_string()),
-            env::var("DATABASE_DB")?,
+            database,
         );
-        return Ok(Self {
-            connection: PgConnection::establish(&database_url)?,
-        });
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .test_on_check_out(true)
+            .build(ConnectionManager::<PgConnection>::new(database_url))?;
+        return Ok(Self { pool, blob_store });
     }
 
-    pub(crate) fn find_min_item_id(&mut self) -> Result<i32> {
+    pub(crate) fn find_min_item_id(&self) -> Result<i32> {
         let min_item_id = diesel::sql_query("SELECT min(id) AS id FROM items")
-            .get_result::<ItemIdRecord>(&mut self.connection)?
+            .get_result::<ItemIdRecord>(&mut self.pool.get()?)?
             .id;
         return Ok(min_item_id);
     }
 
-    pub(crate) fn find_max_item_id(&mut self) -> Result<i32> {
+    pub(crate) fn find_max_item_id(&self) -> Result<i32> {
         let max_item_id = diesel::sql_query("SELECT max(id) AS id FROM items")
-            .get_result::<ItemIdRecord>(&mut self.connection)?
+            .get_result::<ItemIdRecord>(&mut self.pool.get()?)?
             .id;
         return Ok(max_item_id);
     }
 
-    pub(crate) fn find_missing_items(&mut self, min_id: i32, max_id: i32) -> Result<Vec<i32>> {
+    pub(crate) fn find_missing_items(&self, min_id: i32, max_id: i32) -> Result<Vec<i32>> {
         // See: https://stackoverflow.com/questions/12444142/postgresql-how-to-figure-out-missing-numbers-in-a-column-using-generate-series
         let missing_items = diesel::sql_query(
             "SELECT s.i AS id \
@@ -56,14 +67,14 @@ impl Repository {
         )
         .bind::<Integer, _>(min_id)
         .bind::<Integer, _>(max_id)
-        .get_results::<MissingItemRecord>(&mut self.connection)?
+        .get_results::<MissingItemRecord>(&mut self.pool.get()?)?
         .iter()
         .map(|r| r.id)
         .collect();
         return Ok(missing_items);
     }
 
-    pub(crate) fn find_missing_item_urls(&mut self, min_id: i32, max_id: i32) -> Result<Vec<(i32, String)>> {
+    pub(crate) fn find_missing_item_urls(&self, min_id: i32, max_id: i32) -> Result<Vec<(i32, String)>> {
         let missing_item_urls = diesel::sql_query(
             "SELECT s.i AS id, s.u AS url \
             FROM (SELECT id, url FROM items WHERE id >= $1 AND id <= $2 AND url IS NOT NULL ORDER BY id ASC) AS s(i, u) \
@@ -72,23 +83,49 @@ impl Repository {
         )
         .bind::<Integer, _>(min_id)
         .bind::<Integer, _>(max_id)
-        .get_results::<MissingItemUrlRecord>(&mut self.connection)?
+        .get_results::<MissingItemUrlRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.url))
         .collect();
         return Ok(missing_item_urls);
     }
 
-    pub(crate) fn find_summary_missing_items(&mut self, ids: &[i32]) -> Result<Vec<(i32, String, String)>> {
-        let summary_missing_items = diesel::sql_query(format!(
-            "SELECT id, title, item_urls.text \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+    pub(crate) fn find_refreshable_items(&self, min_time: i64) -> Result<Vec<i32>> {
+        let refreshable_items = diesel::sql_query(
+            "SELECT id \
+            FROM items \
+            WHERE time >= $1 \
+            ORDER BY id DESC",
+        )
+        .bind::<BigInt, _>(min_time)
+        .get_results::<RefreshableItemRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+        return Ok(refreshable_items);
+    }
+
+    // Rejected summaries are not missing, but with the current `prompts`,
+    // the summaries generated or rejected by the older ones are also taken as missing
+    pub(crate) fn find_summary_missing_items(
+        &self,
+        ids: &[i32],
+        prompts: Option<&[String]>,
+    ) -> Result<Vec<(i32, String, String)>> {
+        let summary_missing_items = diesel::sql_query(
+            "SELECT id, items.title, item_urls.text \
+            FROM unnest($1) AS s(i) \
             JOIN items ON s.i = items.id \
             JOIN item_urls ON s.i = item_urls.item_id \
-            WHERE title IS NOT NULL AND item_urls.text IS NOT NULL AND summary IS NULL",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
-        .get_results::<SummaryMissingItemRecord>(&mut self.connection)?
+            WHERE items.title IS NOT NULL AND item_urls.text IS NOT NULL \
+            AND ( \
+                (summary IS NULL AND summary_rejection IS NULL) \
+                OR ($2 IS NOT NULL AND NOT COALESCE(summary_prompts <@ $2, false)) \
+            )",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<SummaryMissingItemRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.title, r.text))
         .collect();
@@ -96,128 +133,422 @@ impl Repository {
     }
 
     pub(crate) fn find_summary_missing_items_excluding(
-        &mut self,
+        &self,
         ids: &[i32],
         limit: usize,
+        prompts: Option<&[String]>,
     ) -> Result<Vec<(i32, String, String)>> {
-        let summary_missing_items = diesel::sql_query(format!(
-            "SELECT id, title, item_urls.text \
+        let summary_missing_items = diesel::sql_query(
+            "SELECT id, items.title, item_urls.text \
             FROM items \
             JOIN item_urls ON items.id = item_urls.item_id \
-            WHERE title IS NOT NULL AND item_urls.text IS NOT NULL AND summary IS NULL AND id NOT IN ({}) \
-            ORDER BY id DESC LIMIT {}",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", "),
-            limit
-        ))
-        .get_results::<SummaryMissingItemRecord>(&mut self.connection)?
+            WHERE items.title IS NOT NULL AND item_urls.text IS NOT NULL \
+            AND ( \
+                (summary IS NULL AND summary_rejection IS NULL) \
+                OR ($3 IS NOT NULL AND NOT COALESCE(summary_prompts <@ $3, false)) \
+            ) \
+            AND id <> ALL($1) \
+            ORDER BY id DESC LIMIT $2",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<BigInt, _>(limit as i64)
+        .bind::<Nullable<Array<Text>>, _>(prompts)
+        .get_results::<SummaryMissingItemRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.title, r.text))
         .collect();
         return Ok(summary_missing_items);
     }
 
-    pub(crate) fn find_summary_existing_items(&mut self, limit: usize) -> Result<Vec<i32>> {
-        let summary_existing_items = diesel::sql_query(format!(
+    pub(crate) fn find_summary_existing_items(&self, limit: usize) -> Result<Vec<i32>> {
+        let summary_existing_items = diesel::sql_query(
             "SELECT id \
             FROM items \
             JOIN item_urls ON items.id = item_urls.item_id \
-            WHERE items.text IS NOT NULL OR summary IS NOT NULL \
-            ORDER BY id DESC LIMIT {}",
-            limit
-        ))
-        .get_results::<SummaryExistingItemRecord>(&mut self.connection)?
+            WHERE (items.text IS NOT NULL OR summary IS NOT NULL) AND duplicate_of IS NULL \
+            ORDER BY id DESC LIMIT $1",
+        )
+        .bind::<BigInt, _>(limit as i64)
+        .get_results::<SummaryExistingItemRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id))
         .collect();
         return Ok(summary_existing_items);
     }
 
-    pub(crate) fn find_item_summaries(&mut self, ids: &[i32]) -> Result<Vec<(i32, Option<String>, Option<String>)>> {
-        let item_summaries = diesel::sql_query(format!(
+    pub(crate) fn find_item_summaries(&self, ids: &[i32]) -> Result<Vec<(i32, Option<String>, Option<String>)>> {
+        let item_summaries = diesel::sql_query(
             "SELECT id, items.text, summary \
-            FROM unnest(ARRAY[{}]) AS s(i) \
+            FROM unnest($1) AS s(i) \
             JOIN items ON s.i = items.id \
             JOIN item_urls ON s.i = item_urls.item_id \
             WHERE items.text IS NOT NULL OR summary IS NOT NULL",
-            ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
-        ))
-        .get_results::<ItemSummaryRecord>(&mut self.connection)?
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .get_results::<ItemSummaryRecord>(&mut self.pool.get()?)?
         .into_iter()
         .map(|r| (r.id, r.text, r.summary))
         .collect();
         return Ok(item_summaries);
     }
 
-    pub(crate) fn insert_item(&mut self, item: Item) -> Result<()> {
-        let item_record = InsertItemRecord {
-            id: item.id,
-            deleted: item.deleted,
-            type_: Some(item.type_.context(format!("item.id={}", item.id))?.try_into()?),
-            by: item.by,
-            time: item.time,
-            text: item.text,
-            dead: item.dead,
-            parent: item.parent,
-            poll: item.poll,
-            url: item.url,
-            score: item.score,
-            title: item.title,
-            descendants: item.descendants,
-            created_at: Local::now(),
-            updated_at: Local::now(),
-        };
+    // The item may have been collected in the meantime, e.g. by another replica or by `follow_items`,
+    // in which case the stored one is kept, as it's refreshed by `upsert_item` anyway
+    pub(crate) fn insert_item(&self, item: Item) -> Result<()> {
+        let item_record = InsertItemRecord::try_from(item)?;
         diesel::insert_into(items::table)
             .values(&item_record)
-            .returning(InsertItemRecord::as_returning())
-            .get_result(&mut self.connection)?;
+            .on_conflict(items::id)
+            .do_nothing()
+            .execute(&mut self.pool.get()?)?;
         Ok(())
     }
 
-    pub(crate) fn insert_item_url(&mut self, item_id: i32, item_url: ItemUrl) -> Result<()> {
+    pub(crate) fn upsert_item(&self, item: Item) -> Result<()> {
+        let mut item_record = InsertItemRecord::try_from(item)?;
+        item_record.refreshed_at = Some(Local::now());
+        diesel::insert_into(items::table)
+            .values(&item_record)
+            .on_conflict(items::id)
+            .do_update()
+            .set((
+                items::deleted.eq(excluded(items::deleted)),
+                items::type_.eq(excluded(items::type_)),
+                items::by.eq(excluded(items::by)),
+                items::time.eq(excluded(items::time)),
+                items::text.eq(excluded(items::text)),
+                items::dead.eq(excluded(items::dead)),
+                items::parent.eq(excluded(items::parent)),
+                items::poll.eq(excluded(items::poll)),
+                items::url.eq(excluded(items::url)),
+                items::score.eq(excluded(items::score)),
+                items::title.eq(excluded(items::title)),
+                items::descendants.eq(excluded(items::descendants)),
+                items::updated_at.eq(excluded(items::updated_at)),
+                items::refreshed_at.eq(excluded(items::refreshed_at)),
+                items::kids.eq(excluded(items::kids)),
+                items::parts.eq(excluded(items::parts)),
+            ))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    pub(crate) fn find_retryable_item_urls(
+        &self,
+        max_attempts_num: i32,
+        limit: usize,
+    ) -> Result<Vec<(i32, String, i32)>> {
+        let retryable_item_urls = diesel::sql_query(
+            "SELECT item_id AS id, url, attempts_num \
+            FROM item_urls \
+            JOIN items ON item_urls.item_id = items.id \
+            WHERE url IS NOT NULL AND next_attempt_at <= NOW() AND attempts_num < $1 \
+            ORDER BY item_id DESC LIMIT $2",
+        )
+        .bind::<Integer, _>(max_attempts_num)
+        .bind::<BigInt, _>(limit as i64)
+        .get_results::<RetryableItemUrlRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.url, r.attempts_num))
+        .collect();
+        return Ok(retryable_item_urls);
+    }
+
+    // The URLs which are still to be fetched, with the number of the attempt to fetch them,
+    // where the failed ones are only fetched again when they are due and haven't run out of attempts
+    pub(crate) fn find_fetchable_item_urls(
+        &self,
+        ids: &[i32],
+        max_attempts_num: i32,
+    ) -> Result<Vec<(i32, String, i32)>> {
+        let fetchable_item_urls = diesel::sql_query(
+            "SELECT id, url, COALESCE(item_urls.attempts_num, 0) + 1 AS attempts_num \
+            FROM unnest($1) AS s(i) \
+            JOIN items ON s.i = items.id \
+            LEFT JOIN item_urls ON s.i = item_urls.item_id \
+            WHERE url IS NOT NULL AND ( \
+                item_urls.item_id IS NULL \
+                OR (item_urls.next_attempt_at <= NOW() AND item_urls.attempts_num < $2) \
+            )",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .bind::<Integer, _>(max_attempts_num)
+        .get_results::<RetryableItemUrlRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.url, r.attempts_num))
+        .collect();
+        return Ok(fetchable_item_urls);
+    }
+
+    pub(crate) fn upsert_item_url(
+        &self,
+        item_id: i32,
+        item_url: ItemUrl,
+        attempts_num: i32,
+        next_attempt_at: Option<DateTime<Local>>,
+    ) -> Result<()> {
         let mut item_url_record = InsertItemUrlRecord {
             item_id,
-            html: None,
             text: None,
             summary: None,
-            status_code: None,
             status_note: None,
             created_at: Local::now(),
             updated_at: Local::now(),
+            fetch_strategy: None,
+            title: None,
+            author: None,
+            published_at: None,
+            canonical_url: None,
+            status: None,
+            attempts_num,
+            next_attempt_at,
+            html_hash: None,
         };
-        // TODO: Use enum to encapsulate `status_code` value
         match item_url {
-            ItemUrl::Finished { html, text } => {
-                item_url_record.html = Some(html);
+            ItemUrl::Finished {
+                html,
+                text,
+                metadata,
+                strategy,
+            } => {
+                item_url_record.html_hash = match html {
+                    Some(html) => Some(self.insert_blob(html.as_bytes())?),
+                    None => None,
+                };
                 item_url_record.text = Some(text);
-                item_url_record.status_code = Some(0);
-            }
-            ItemUrl::Skipped { note } => {
-                item_url_record.status_note = Some(note);
-                item_url_record.status_code = Some(1);
+                item_url_record.title = metadata.title;
+                item_url_record.author = metadata.author;
+                item_url_record.published_at = metadata.published_at;
+                item_url_record.canonical_url = metadata.canonical_url;
+                item_url_record.status = Some(ItemUrlStatusValue::Finished);
+                item_url_record.fetch_strategy = Some(strategy.into());
             }
-            ItemUrl::Canceled { note } => {
+            ItemUrl::Failed { failure, note } => {
                 item_url_record.status_note = Some(note);
-                item_url_record.status_code = Some(2);
+                item_url_record.status = Some(failure.into());
             }
         }
         diesel::insert_into(item_urls::table)
             .values(&item_url_record)
-            .returning(InsertItemUrlRecord::as_returning())
-            .get_result(&mut self.connection)?;
+            .on_conflict(item_urls::item_id)
+            .do_update()
+            .set((
+                item_urls::text.eq(excluded(item_urls::text)),
+                item_urls::status_note.eq(excluded(item_urls::status_note)),
+                item_urls::updated_at.eq(excluded(item_urls::updated_at)),
+                item_urls::fetch_strategy.eq(excluded(item_urls::fetch_strategy)),
+                item_urls::title.eq(excluded(item_urls::title)),
+                item_urls::author.eq(excluded(item_urls::author)),
+                item_urls::published_at.eq(excluded(item_urls::published_at)),
+                item_urls::canonical_url.eq(excluded(item_urls::canonical_url)),
+                item_urls::status.eq(excluded(item_urls::status)),
+                item_urls::attempts_num.eq(excluded(item_urls::attempts_num)),
+                item_urls::next_attempt_at.eq(excluded(item_urls::next_attempt_at)),
+                item_urls::html_hash.eq(excluded(item_urls::html_hash)),
+            ))
+            .execute(&mut self.pool.get()?)?;
         Ok(())
     }
 
-    pub(crate) fn update_item_url(&mut self, item_id: i32, summary: String) -> Result<()> {
-        let update_item_url_record = UpdateItemUrlRecord {
-            summary: Some(summary),
+    // Takes over a finished item URL with the same normalized URL, instead of fetching the page again
+    pub(crate) fn reuse_item_url(&self, item_id: i32, normalized_url: &str, attempts_num: i32) -> Result<Option<i32>> {
+        let original_id = diesel::sql_query(
+            "INSERT INTO item_urls ( \
+                item_id, html_hash, text, summary, topics, summary_prompts, summary_rejection, created_at, updated_at, \
+                fetch_strategy, title, author, published_at, canonical_url, status, attempts_num, normalized_url, \
+                content_hash, duplicate_of \
+            ) \
+            SELECT $1, html_hash, text, summary, topics, summary_prompts, summary_rejection, NOW(), NOW(), \
+                fetch_strategy, title, author, published_at, canonical_url, status, $2, normalized_url, content_hash, \
+                COALESCE(duplicate_of, item_id) \
+            FROM item_urls \
+            WHERE normalized_url = $3 AND status = 'finished' AND item_id <> $1 \
+            ORDER BY item_id ASC LIMIT 1 \
+            ON CONFLICT (item_id) DO UPDATE SET \
+                html_hash = excluded.html_hash, text = excluded.text, summary = excluded.summary, topics = excluded.topics, \
+                summary_prompts = excluded.summary_prompts, summary_rejection = excluded.summary_rejection, \
+                status_note = NULL, \
+                updated_at = excluded.updated_at, fetch_strategy = excluded.fetch_strategy, title = excluded.title, \
+                author = excluded.author, published_at = excluded.published_at, canonical_url = excluded.canonical_url, \
+                status = excluded.status, attempts_num = excluded.attempts_num, next_attempt_at = NULL, \
+                normalized_url = excluded.normalized_url, content_hash = excluded.content_hash, \
+                duplicate_of = excluded.duplicate_of \
+            RETURNING duplicate_of",
+        )
+        .bind::<Integer, _>(item_id)
+        .bind::<Integer, _>(attempts_num)
+        .bind::<Text, _>(normalized_url)
+        .get_results::<DuplicateItemUrlRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .find_map(|r| r.duplicate_of);
+        return Ok(original_id);
+    }
+
+    // Links a freshly fetched item URL to the first one with the same normalized URL or content, sharing its summary.
+    // Items already having duplicates are left alone, so that duplicates never chain. The content hash is stored
+    // either way, but only matched if `matches_content`, as short texts are alike across unrelated pages.
+    pub(crate) fn link_duplicate_item_url(
+        &self,
+        item_id: i32,
+        normalized_url: Option<&str>,
+        content_hash: Option<&str>,
+        matches_content: bool,
+    ) -> Result<Option<i32>> {
+        let original_id = diesel::sql_query(
+            "WITH original AS ( \
+                SELECT COALESCE(duplicate_of, item_id) AS id \
+                FROM item_urls \
+                WHERE item_id <> $1 AND status = 'finished' AND (normalized_url = $2 OR ($4 AND content_hash = $3)) \
+                AND NOT EXISTS (SELECT 1 FROM item_urls WHERE duplicate_of = $1) \
+                ORDER BY item_id ASC LIMIT 1 \
+            ) \
+            UPDATE item_urls \
+            SET normalized_url = $2, content_hash = $3, duplicate_of = (SELECT id FROM original), \
+                summary = COALESCE( \
+                    (SELECT summary FROM item_urls WHERE item_id = (SELECT id FROM original)), \
+                    item_urls.summary \
+                ), \
+                topics = COALESCE( \
+                    (SELECT topics FROM item_urls WHERE item_id = (SELECT id FROM original)), \
+                    item_urls.topics \
+                ), \
+                summary_prompts = COALESCE( \
+                    (SELECT summary_prompts FROM item_urls WHERE item_id = (SELECT id FROM original)), \
+                    item_urls.summary_prompts \
+                ), \
+                summary_rejection = COALESCE( \
+                    (SELECT summary_rejection FROM item_urls WHERE item_id = (SELECT id FROM original)), \
+                    item_urls.summary_rejection \
+                ) \
+            WHERE item_id = $1 \
+            RETURNING duplicate_of",
+        )
+        .bind::<Integer, _>(item_id)
+        .bind::<Nullable<Text>, _>(normalized_url)
+        .bind::<Nullable<Text>, _>(content_hash)
+        .bind::<Bool, _>(matches_content)
+        .get_results::<DuplicateItemUrlRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .find_map(|r| r.duplicate_of);
+        return Ok(original_id);
+    }
+
+    // Only item URLs fetched as HTML can be re-extracted, i.e. not PDFs
+    pub(crate) fn find_reextractable_item_urls(&self, min_id: i32, max_id: i32) -> Result<Vec<(i32, Option<String>)>> {
+        let reextractable_item_urls = diesel::sql_query(
+            "SELECT item_id AS id, text \
+            FROM item_urls \
+            WHERE item_id >= $1 AND item_id <= $2 AND html_hash IS NOT NULL \
+            ORDER BY item_id ASC",
+        )
+        .bind::<Integer, _>(min_id)
+        .bind::<Integer, _>(max_id)
+        .get_results::<ReextractableItemUrlRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| (r.id, r.text))
+        .collect();
+        return Ok(reextractable_item_urls);
+    }
+
+    pub(crate) fn find_item_url_html(&self, item_id: i32) -> Result<Option<String>> {
+        let html_hash = item_urls::table
+            .filter(item_urls::item_id.eq(item_id))
+            .select(item_urls::html_hash)
+            .first::<Option<String>>(&mut self.pool.get()?)
+            .optional()?
+            .flatten();
+        let html = match html_hash {
+            Some(html_hash) => self.find_blob(&html_hash)?,
+            None => return Ok(None),
+        };
+        match html {
+            Some(html) => Ok(Some(String::from_utf8(html)?)),
+            None => bail!("Blob of item URL is missing (id={})", item_id),
+        }
+    }
+
+    pub(crate) fn update_item_url_text(
+        &self,
+        item_id: i32,
+        text: String,
+        metadata: ArticleMetadata,
+        content_hash: String,
+    ) -> Result<()> {
+        let update_item_url_text_record = UpdateItemUrlTextRecord {
+            text: Some(text),
+            title: metadata.title,
+            author: metadata.author,
+            published_at: metadata.published_at,
+            canonical_url: metadata.canonical_url,
+            content_hash: Some(content_hash),
             updated_at: Local::now(),
         };
         diesel::update(item_urls::table)
             .filter(item_urls::item_id.eq(item_id))
-            .set(update_item_url_record)
-            .execute(&mut self.connection)?;
+            .set(update_item_url_text_record)
+            .execute(&mut self.pool.get()?)?;
         Ok(())
     }
+
+    // Lets the summary be generated again from the current text
+    pub(crate) fn invalidate_item_url_summary(&self, item_id: i32) -> Result<()> {
+        diesel::update(item_urls::table)
+            .filter(item_urls::item_id.eq(item_id))
+            .set((
+                item_urls::summary.eq(None::<String>),
+                item_urls::topics.eq(None::<Vec<Option<String>>>),
+                item_urls::summary_prompts.eq(None::<Vec<Option<String>>>),
+                item_urls::summary_rejection.eq(None::<String>),
+            ))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    // The summary is shared by the original item and all of its duplicates, whose ids are returned
+    pub(crate) fn update_item_url(&self, item_id: i32, summary: Summary, prompts: Vec<String>) -> Result<Vec<i32>> {
+        let updated_ids = diesel::sql_query(
+            "WITH original AS (SELECT COALESCE(duplicate_of, item_id) AS id FROM item_urls WHERE item_id = $5) \
+            UPDATE item_urls \
+            SET summary = $1, topics = $2, summary_prompts = $3, summary_rejection = NULL, updated_at = $4 \
+            WHERE item_id = $5 OR item_id = (SELECT id FROM original) OR duplicate_of = (SELECT id FROM original) \
+            RETURNING item_id AS id",
+        )
+        .bind::<Text, _>(summary.summary)
+        .bind::<Array<Text>, _>(summary.topics)
+        .bind::<Array<Text>, _>(prompts)
+        .bind::<Timestamptz, _>(Local::now())
+        .bind::<Integer, _>(item_id)
+        .get_results::<ItemIdRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+        return Ok(updated_ids);
+    }
+
+    // Like the summary, the rejection is shared by the original item and all of its duplicates
+    pub(crate) fn reject_item_url_summary(
+        &self,
+        item_id: i32,
+        reason: String,
+        prompts: Vec<String>,
+    ) -> Result<Vec<i32>> {
+        let rejected_ids = diesel::sql_query(
+            "WITH original AS (SELECT COALESCE(duplicate_of, item_id) AS id FROM item_urls WHERE item_id = $4) \
+            UPDATE item_urls \
+            SET summary = NULL, topics = NULL, summary_prompts = $1, summary_rejection = $2, updated_at = $3 \
+            WHERE item_id = $4 OR item_id = (SELECT id FROM original) OR duplicate_of = (SELECT id FROM original) \
+            RETURNING item_id AS id",
+        )
+        .bind::<Array<Text>, _>(prompts)
+        .bind::<Text, _>(reason)
+        .bind::<Timestamptz, _>(Local::now())
+        .bind::<Integer, _>(item_id)
+        .get_results::<ItemIdRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+        return Ok(rejected_ids);
+    }
 }
 
 #[derive(AsExpression, FromSqlRow, Debug)]
@@ -265,6 +596,125 @@ impl FromSql<ItemType, Pg> for ItemTypeValue {
     }
 }
 
+#[derive(AsExpression, FromSqlRow, Debug)]
+#[diesel(sql_type = FetchStrategy)]
+enum FetchStrategyValue {
+    Http,
+    Browser,
+    Pdf,
+}
+
+impl From<service::FetchStrategy> for FetchStrategyValue {
+    fn from(value: service::FetchStrategy) -> Self {
+        match value {
+            service::FetchStrategy::Http => FetchStrategyValue::Http,
+            service::FetchStrategy::Browser => FetchStrategyValue::Browser,
+            service::FetchStrategy::Pdf => FetchStrategyValue::Pdf,
+        }
+    }
+}
+
+impl TryFrom<String> for FetchStrategyValue {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "http" => Ok(FetchStrategyValue::Http),
+            "browser" => Ok(FetchStrategyValue::Browser),
+            "pdf" => Ok(FetchStrategyValue::Pdf),
+            _ => bail!(""),
+        }
+    }
+}
+
+impl ToSql<FetchStrategy, Pg> for FetchStrategyValue {
+    fn to_sql<'b>(&'b self, out: &mut diesel::serialize::Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        match *self {
+            FetchStrategyValue::Http => out.write_all(b"http")?,
+            FetchStrategyValue::Browser => out.write_all(b"browser")?,
+            FetchStrategyValue::Pdf => out.write_all(b"pdf")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<FetchStrategy, Pg> for FetchStrategyValue {
+    fn from_sql(bytes: <Pg as diesel::backend::Backend>::RawValue<'_>) -> diesel::deserialize::Result<Self> {
+        Ok(<String as FromSql<Text, Pg>>::from_sql(bytes)?.try_into()?)
+    }
+}
+
+#[derive(AsExpression, FromSqlRow, Debug)]
+#[diesel(sql_type = ItemUrlStatus)]
+enum ItemUrlStatusValue {
+    Finished,
+    UnsupportedContent,
+    ExtractionFailure,
+    Disallowed,
+    HttpError,
+    Timeout,
+    DnsFailure,
+    ConnectionFailure,
+    UnknownFailure,
+}
+
+impl From<ItemUrlFailure> for ItemUrlStatusValue {
+    fn from(value: ItemUrlFailure) -> Self {
+        match value {
+            ItemUrlFailure::UnsupportedContent => ItemUrlStatusValue::UnsupportedContent,
+            ItemUrlFailure::ExtractionFailure => ItemUrlStatusValue::ExtractionFailure,
+            ItemUrlFailure::Disallowed => ItemUrlStatusValue::Disallowed,
+            ItemUrlFailure::Deferred(_) | ItemUrlFailure::HttpError(_) => ItemUrlStatusValue::HttpError,
+            ItemUrlFailure::Timeout => ItemUrlStatusValue::Timeout,
+            ItemUrlFailure::DnsFailure => ItemUrlStatusValue::DnsFailure,
+            ItemUrlFailure::ConnectionFailure => ItemUrlStatusValue::ConnectionFailure,
+            ItemUrlFailure::UnknownFailure => ItemUrlStatusValue::UnknownFailure,
+        }
+    }
+}
+
+impl TryFrom<String> for ItemUrlStatusValue {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "finished" => Ok(ItemUrlStatusValue::Finished),
+            "unsupported_content" => Ok(ItemUrlStatusValue::UnsupportedContent),
+            "extraction_failure" => Ok(ItemUrlStatusValue::ExtractionFailure),
+            "disallowed" => Ok(ItemUrlStatusValue::Disallowed),
+            "http_error" => Ok(ItemUrlStatusValue::HttpError),
+            "timeout" => Ok(ItemUrlStatusValue::Timeout),
+            "dns_failure" => Ok(ItemUrlStatusValue::DnsFailure),
+            "connection_failure" => Ok(ItemUrlStatusValue::ConnectionFailure),
+            "unknown_failure" => Ok(ItemUrlStatusValue::UnknownFailure),
+            _ => bail!(""),
+        }
+    }
+}
+
+impl ToSql<ItemUrlStatus, Pg> for ItemUrlStatusValue {
+    fn to_sql<'b>(&'b self, out: &mut diesel::serialize::Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        match *self {
+            ItemUrlStatusValue::Finished => out.write_all(b"finished")?,
+            ItemUrlStatusValue::UnsupportedContent => out.write_all(b"unsupported_content")?,
+            ItemUrlStatusValue::ExtractionFailure => out.write_all(b"extraction_failure")?,
+            ItemUrlStatusValue::Disallowed => out.write_all(b"disallowed")?,
+            ItemUrlStatusValue::HttpError => out.write_all(b"http_error")?,
+            ItemUrlStatusValue::Timeout => out.write_all(b"timeout")?,
+            ItemUrlStatusValue::DnsFailure => out.write_all(b"dns_failure")?,
+            ItemUrlStatusValue::ConnectionFailure => out.write_all(b"connection_failure")?,
+            ItemUrlStatusValue::UnknownFailure => out.write_all(b"unknown_failure")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<ItemUrlStatus, Pg> for ItemUrlStatusValue {
+    fn from_sql(bytes: <Pg as diesel::backend::Backend>::RawValue<'_>) -> diesel::deserialize::Result<Self> {
+        Ok(<String as FromSql<Text, Pg>>::from_sql(bytes)?.try_into()?)
+    }
+}
+
 #[derive(QueryableByName)]
 struct ItemIdRecord {
     #[diesel(sql_type = Integer)]
@@ -285,6 +735,22 @@ struct MissingItemUrlRecord {
     url: String,
 }
 
+#[derive(QueryableByName)]
+struct RefreshableItemRecord {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+#[derive(QueryableByName)]
+struct RetryableItemUrlRecord {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text)]
+    url: String,
+    #[diesel(sql_type = Integer)]
+    attempts_num: i32,
+}
+
 #[derive(QueryableByName)]
 struct SummaryMissingItemRecord {
     #[diesel(sql_type = Integer)]
@@ -330,6 +796,36 @@ struct InsertItemRecord {
     descendants: Option<i32>,
     created_at: DateTime<Local>,
     updated_at: DateTime<Local>,
+    refreshed_at: Option<DateTime<Local>>,
+    kids: Option<Vec<Option<i32>>>,
+    parts: Option<Vec<Option<i32>>>,
+}
+
+impl TryFrom<Item> for InsertItemRecord {
+    type Error = Error;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(InsertItemRecord {
+            id: item.id,
+            deleted: item.deleted,
+            type_: Some(item.type_.context(format!("item.id={}", item.id))?.try_into()?),
+            by: item.by,
+            time: item.time,
+            text: item.text,
+            dead: item.dead,
+            parent: item.parent,
+            poll: item.poll,
+            url: item.url,
+            score: item.score,
+            title: item.title,
+            descendants: item.descendants,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            refreshed_at: None,
+            kids: item.kids.map(|k| k.into_iter().map(Some).collect()),
+            parts: item.parts.map(|p| p.into_iter().map(Some).collect()),
+        })
+    }
 }
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -337,19 +833,137 @@ struct InsertItemRecord {
 #[diesel(check_for_backend(Pg))]
 struct InsertItemUrlRecord {
     item_id: i32,
-    html: Option<String>,
     text: Option<String>,
     summary: Option<String>,
-    status_code: Option<i32>,
     status_note: Option<String>,
     created_at: DateTime<Local>,
     updated_at: DateTime<Local>,
+    fetch_strategy: Option<FetchStrategyValue>,
+    title: Option<String>,
+    author: Option<String>,
+    published_at: Option<DateTime<Local>>,
+    canonical_url: Option<String>,
+    status: Option<ItemUrlStatusValue>,
+    attempts_num: i32,
+    next_attempt_at: Option<DateTime<Local>>,
+    html_hash: Option<String>,
 }
 
+#[derive(QueryableByName)]
+struct ReextractableItemUrlRecord {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    text: Option<String>,
+}
+
+// `None` leaves a column untouched, so metadata no longer found is kept as is
 #[derive(AsChangeset)]
 #[diesel(table_name = item_urls)]
 #[diesel(check_for_backend(Pg))]
-struct UpdateItemUrlRecord {
-    summary: Option<String>,
+struct UpdateItemUrlTextRecord {
+    text: Option<String>,
+    title: Option<String>,
+    author: Option<String>,
+    published_at: Option<DateTime<Local>>,
+    canonical_url: Option<String>,
+    content_hash: Option<String>,
     updated_at: DateTime<Local>,
 }
+
+#[derive(QueryableByName)]
+struct DuplicateItemUrlRecord {
+    #[diesel(sql_type = Nullable<Integer>)]
+    duplicate_of: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::tests::{connect, insert_stories};
+
+    #[test]
+    fn finds_summary_missing_items_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_000_000..1_900_020_000).collect();
+        insert_stories(&repo, &ids);
+        assert!(repo.find_summary_missing_items(&[], None).unwrap().is_empty());
+        assert_eq!(repo.find_summary_missing_items(&ids, None).unwrap().len(), ids.len());
+    }
+
+    #[test]
+    fn finds_summary_missing_items_excluding_ids() {
+        let Some(repo) = connect() else { return };
+        // Above the ids of the other tests, as the items are listed from the latest
+        let ids: Vec<i32> = (2_100_000_000..2_100_020_010).collect();
+        insert_stories(&repo, &ids);
+        let found = repo.find_summary_missing_items_excluding(&[], 1, None).unwrap();
+        assert_eq!(
+            found.into_iter().map(|(id, _, _)| id).collect::<Vec<_>>(),
+            [2_100_020_009]
+        );
+        let found = repo.find_summary_missing_items_excluding(&ids[10..], 10, None).unwrap();
+        assert_eq!(
+            found.into_iter().map(|(id, _, _)| id).rev().collect::<Vec<_>>(),
+            ids[..10]
+        );
+    }
+
+    #[test]
+    fn finds_item_summaries_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_100_000..1_900_120_000).collect();
+        insert_stories(&repo, &ids);
+        diesel::sql_query("UPDATE item_urls SET summary = 'Summary' WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(&ids)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        assert!(repo.find_item_summaries(&[]).unwrap().is_empty());
+        assert_eq!(repo.find_item_summaries(&ids).unwrap().len(), ids.len());
+    }
+
+    #[test]
+    fn finds_fetchable_item_urls_by_ids() {
+        let Some(repo) = connect() else { return };
+        let ids: Vec<i32> = (1_900_200_000..1_900_220_000).collect();
+        insert_stories(&repo, &ids);
+        // The urls fetched already are retried only once their next attempt is due
+        let unfetched_ids: Vec<i32> = ids.iter().copied().filter(|id| id % 2 == 0).collect();
+        diesel::sql_query("DELETE FROM item_urls WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(&unfetched_ids)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        assert!(repo.find_fetchable_item_urls(&[], 5).unwrap().is_empty());
+        let found = repo.find_fetchable_item_urls(&ids, 5).unwrap();
+        assert_eq!(found.len(), unfetched_ids.len());
+        assert!(found
+            .iter()
+            .all(|(id, _, attempts_num)| id % 2 == 0 && *attempts_num == 1));
+    }
+
+    #[test]
+    fn links_duplicate_item_urls() {
+        let Some(repo) = connect() else { return };
+        let ids = vec![1_900_900_000, 1_900_900_001, 1_900_900_002];
+        insert_stories(&repo, &ids);
+        diesel::sql_query("UPDATE item_urls SET status = 'finished' WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(&ids)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        // The original was rejected, which its duplicates share rather than being summarized again
+        diesel::sql_query("UPDATE item_urls SET summary_rejection = 'Too short' WHERE item_id = $1")
+            .bind::<Integer, _>(ids[0])
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        let original_id = repo.link_duplicate_item_url(ids[0], Some("example.com/a"), Some("hash"), true);
+        assert_eq!(original_id.unwrap(), None);
+        // Short texts are only matched by their URL
+        let original_id = repo.link_duplicate_item_url(ids[1], Some("example.com/b"), Some("hash"), false);
+        assert_eq!(original_id.unwrap(), None);
+        let original_id = repo.link_duplicate_item_url(ids[1], Some("example.com/b"), Some("hash"), true);
+        assert_eq!(original_id.unwrap(), Some(ids[0]));
+        let original_id = repo.link_duplicate_item_url(ids[2], Some("example.com/a"), Some("other"), false);
+        assert_eq!(original_id.unwrap(), Some(ids[0]));
+        assert!(repo.find_summary_missing_items(&ids, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+    sql_types::{BigInt, Bool},
+};
+
+use super::Repository;
+
+// Holds on to the connection which has taken the lock, as the lock belongs to the session of the connection
+pub(crate) struct AdvisoryLock {
+    connection: PooledConnection<ConnectionManager<PgConnection>>,
+    key: i64,
+}
+
+impl Repository {
+    // Session-level, i.e. held until it's unlocked or the connection is closed, so a crashed process never keeps it
+    pub(crate) fn try_advisory_lock(&self, key: i64) -> Result<Option<AdvisoryLock>> {
+        let mut connection = self.pool.get()?;
+        let locked = diesel::sql_query("SELECT pg_try_advisory_lock($1) AS locked")
+            .bind::<BigInt, _>(key)
+            .get_result::<AdvisoryLockRecord>(&mut connection)?
+            .locked;
+        if !locked {
+            return Ok(None);
+        }
+        return Ok(Some(AdvisoryLock { connection, key }));
+    }
+}
+
+impl AdvisoryLock {
+    pub(crate) fn unlock(mut self) -> Result<()> {
+        diesel::sql_query("SELECT pg_advisory_unlock($1) AS locked")
+            .bind::<BigInt, _>(self.key)
+            .get_result::<AdvisoryLockRecord>(&mut self.connection)?;
+        Ok(())
+    }
+}
+
+#[derive(QueryableByName)]
+struct AdvisoryLockRecord {
+    #[diesel(sql_type = Bool)]
+    locked: bool,
+}
@@ -1,8 +1,90 @@
-use diesel::PgConnection;
+use anyhow::Result;
+use diesel::{
+    r2d2::{ConnectionManager, Pool},
+    PgConnection,
+};
+
+use self::blob::BlobStore;
 
 pub(crate) mod analysis;
+pub(crate) mod blob;
+pub(crate) mod checkpoint;
+pub(crate) mod generation;
 pub(crate) mod item; // Core
+pub(crate) mod lock;
+pub(crate) mod story_list;
+pub(crate) mod task;
+pub(crate) mod user;
 
+// Cheap to clone, as the clones share the same pool of connections
+#[derive(Clone)]
 pub(crate) struct Repository {
-    connection: PgConnection,
+    pool: Pool<ConnectionManager<PgConnection>>,
+    blob_store: BlobStore,
+}
+
+impl Repository {
+    // Diesel blocks on every query, so queries are run on the blocking threads of tokio, each with a connection of its own
+    pub(crate) async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Repository) -> Result<T> + Send + 'static,
+    {
+        let repo = self.clone();
+        tokio::task::spawn_blocking(move || f(&repo)).await?
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use diesel::{sql_types::*, RunQueryDsl};
+
+    use super::{BlobStore, Repository};
+
+    // The tests run against the database named by `DATABASE_TEST_DB`, which is to be migrated beforehand,
+    // and are skipped without it
+    pub(crate) fn connect() -> Option<Repository> {
+        let database = std::env::var("DATABASE_TEST_DB").ok()?;
+        Some(Repository::connect(&database, BlobStore::Database(None), 10).unwrap())
+    }
+
+    pub(crate) fn delete_items(repo: &Repository, ids: &[i32]) {
+        let conn = &mut repo.pool.get().unwrap();
+        diesel::sql_query("DELETE FROM analyses WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+        diesel::sql_query("DELETE FROM item_urls WHERE item_id = ANY($1)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+        diesel::sql_query("DELETE FROM items WHERE id = ANY($1)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+    }
+
+    pub(crate) fn delete_checkpoint(repo: &Repository, name: &str) {
+        diesel::sql_query("DELETE FROM checkpoints WHERE name = $1")
+            .bind::<Text, _>(name)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+    }
+
+    // Stories with a text each, where every test takes ids of its own, as the tests share the database
+    pub(crate) fn insert_stories(repo: &Repository, ids: &[i32]) {
+        delete_items(repo, ids);
+        let conn = &mut repo.pool.get().unwrap();
+        diesel::sql_query(
+            "INSERT INTO items (id, type, title, url, time) \
+            SELECT i, 'story', 'Story ' || i, 'https://example.com/' || i, i FROM unnest($1) AS s(i)",
+        )
+        .bind::<Array<Integer>, _>(ids)
+        .execute(conn)
+        .unwrap();
+        diesel::sql_query("INSERT INTO item_urls (item_id, text) SELECT i, 'Text ' || i FROM unnest($1) AS s(i)")
+            .bind::<Array<Integer>, _>(ids)
+            .execute(conn)
+            .unwrap();
+    }
 }
@@ -0,0 +1,60 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use diesel::{
+    expression::AsExpression,
+    pg::Pg,
+    prelude::*,
+    serialize::{IsNull, ToSql},
+};
+
+use super::Repository;
+use crate::{
+    schema::{sql_types::StoryListType, story_lists},
+    service::StoryList,
+};
+
+impl Repository {
+    pub(crate) fn insert_story_list(
+        &self,
+        list: StoryList,
+        item_ids: Vec<i32>,
+        created_at: DateTime<Local>,
+    ) -> Result<()> {
+        let story_list_record = InsertStoryListRecord {
+            type_: list.into(),
+            item_ids: item_ids.into_iter().map(Some).collect(),
+            created_at,
+        };
+        diesel::insert_into(story_lists::table)
+            .values(&story_list_record)
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+}
+
+#[derive(AsExpression, Debug)]
+#[diesel(sql_type = StoryListType)]
+struct StoryListTypeValue(StoryList);
+
+impl From<StoryList> for StoryListTypeValue {
+    fn from(value: StoryList) -> Self {
+        StoryListTypeValue(value)
+    }
+}
+
+impl ToSql<StoryListType, Pg> for StoryListTypeValue {
+    fn to_sql<'b>(&'b self, out: &mut diesel::serialize::Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.0.name().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = story_lists)]
+struct InsertStoryListRecord {
+    type_: StoryListTypeValue,
+    item_ids: Vec<Option<i32>>,
+    created_at: DateTime<Local>,
+}
@@ -0,0 +1,390 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use diesel::{
+    expression::AsExpression,
+    pg::Pg,
+    prelude::*,
+    serialize::{IsNull, ToSql},
+    sql_types::*,
+};
+
+use super::Repository;
+use crate::{
+    schema::{sql_types::TaskKind as TaskKindType, tasks},
+    service::{Task, TaskKind},
+};
+
+impl Repository {
+    // Items which already have a task of the kind, including a dead one, are skipped
+    pub(crate) fn enqueue_tasks(&self, kind: TaskKind, item_ids: &[i32]) -> Result<usize> {
+        let mut connection = self.pool.get()?;
+        let mut enqueued_num = 0;
+        // Keeps the number of bind parameters of a statement under the limit of Postgres
+        for chunk in item_ids.chunks(1000) {
+            let now = Local::now();
+            let task_records = chunk
+                .iter()
+                .map(|item_id| InsertTaskRecord {
+                    kind: kind.into(),
+                    item_id: *item_id,
+                    run_at: now,
+                    created_at: now,
+                    updated_at: now,
+                })
+                .collect::<Vec<InsertTaskRecord>>();
+            enqueued_num += diesel::insert_into(tasks::table)
+                .values(&task_records)
+                .on_conflict_do_nothing()
+                .execute(&mut connection)?;
+        }
+        return Ok(enqueued_num);
+    }
+
+    // Takes over the running tasks whose worker has stopped sending heartbeats as well,
+    // unless they have run out of attempts, which are dead-lettered instead.
+    pub(crate) fn claim_tasks(
+        &self,
+        kind: TaskKind,
+        worker: &str,
+        stale_before: DateTime<Local>,
+        max_attempts_num: i32,
+        limit: usize,
+    ) -> Result<Vec<Task>> {
+        let mut connection = self.pool.get()?;
+        diesel::sql_query(
+            "UPDATE tasks \
+            SET status = 'dead', last_error = 'The worker stopped sending heartbeats', worker = NULL, updated_at = NOW() \
+            WHERE kind = $1 AND status = 'running' AND heartbeat_at < $2 AND attempts_num >= $3",
+        )
+        .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+        .bind::<Timestamptz, _>(stale_before)
+        .bind::<Integer, _>(max_attempts_num)
+        .execute(&mut connection)?;
+        let claimed_tasks = diesel::sql_query(
+            "UPDATE tasks \
+            SET status = 'running', worker = $2, attempts_num = attempts_num + 1, heartbeat_at = NOW(), updated_at = NOW() \
+            WHERE id IN (\
+                SELECT id FROM tasks \
+                WHERE kind = $1 AND (status = 'queued' AND run_at <= NOW() OR status = 'running' AND heartbeat_at < $3) \
+                ORDER BY run_at ASC, id ASC LIMIT $4 \
+                FOR UPDATE SKIP LOCKED\
+            ) \
+            RETURNING id, item_id, attempts_num",
+        )
+        .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+        .bind::<Text, _>(worker)
+        .bind::<Timestamptz, _>(stale_before)
+        .bind::<BigInt, _>(limit as i64)
+        .get_results::<ClaimedTaskRecord>(&mut connection)?
+        .into_iter()
+        .map(|r| Task {
+            id: r.id,
+            item_id: r.item_id,
+            attempts_num: r.attempts_num,
+        })
+        .collect();
+        return Ok(claimed_tasks);
+    }
+
+    pub(crate) fn heartbeat_tasks(&self, worker: &str) -> Result<()> {
+        diesel::sql_query("UPDATE tasks SET heartbeat_at = NOW() WHERE worker = $1 AND status = 'running'")
+            .bind::<Text, _>(worker)
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    // Returns false when the worker has lost the task, i.e. it has been taken over by another worker
+    // after missing heartbeats, which the task is then left to
+    pub(crate) fn finish_task(&self, id: i32, worker: &str) -> Result<bool> {
+        let finished_num = diesel::sql_query("DELETE FROM tasks WHERE id = $1 AND worker = $2 AND status = 'running'")
+            .bind::<Integer, _>(id)
+            .bind::<Text, _>(worker)
+            .execute(&mut self.pool.get()?)?;
+        Ok(finished_num > 0)
+    }
+
+    // The task is dead-lettered once it has run out of attempts, otherwise it's run again at `run_at`.
+    // Returns false when the worker has lost the task, as `finish_task` does.
+    pub(crate) fn fail_task(
+        &self,
+        id: i32,
+        worker: &str,
+        max_attempts_num: i32,
+        run_at: DateTime<Local>,
+        error: &str,
+    ) -> Result<bool> {
+        let failed_num = diesel::sql_query(
+            "UPDATE tasks \
+            SET status = CASE WHEN attempts_num >= $3 THEN 'dead'::task_status ELSE 'queued'::task_status END, \
+            run_at = $4, last_error = $5, worker = NULL, heartbeat_at = NULL, updated_at = NOW() \
+            WHERE id = $1 AND worker = $2 AND status = 'running'",
+        )
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(worker)
+        .bind::<Integer, _>(max_attempts_num)
+        .bind::<Timestamptz, _>(run_at)
+        .bind::<Text, _>(error)
+        .execute(&mut self.pool.get()?)?;
+        Ok(failed_num > 0)
+    }
+
+    pub(crate) fn requeue_dead_tasks(&self, kind: Option<TaskKind>) -> Result<usize> {
+        let requeued_num = match kind {
+            Some(kind) => diesel::sql_query(
+                "UPDATE tasks \
+                SET status = 'queued', attempts_num = 0, run_at = NOW(), last_error = NULL, updated_at = NOW() \
+                WHERE status = 'dead' AND kind = $1",
+            )
+            .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+            .execute(&mut self.pool.get()?)?,
+            None => diesel::sql_query(
+                "UPDATE tasks \
+                SET status = 'queued', attempts_num = 0, run_at = NOW(), last_error = NULL, updated_at = NOW() \
+                WHERE status = 'dead'",
+            )
+            .execute(&mut self.pool.get()?)?,
+        };
+        return Ok(requeued_num);
+    }
+}
+
+#[derive(AsExpression, Debug)]
+#[diesel(sql_type = TaskKindType)]
+struct TaskKindValue(TaskKind);
+
+impl From<TaskKind> for TaskKindValue {
+    fn from(value: TaskKind) -> Self {
+        TaskKindValue(value)
+    }
+}
+
+impl ToSql<TaskKindType, Pg> for TaskKindValue {
+    fn to_sql<'b>(&'b self, out: &mut diesel::serialize::Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        out.write_all(self.0.name().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = tasks)]
+struct InsertTaskRecord {
+    kind: TaskKindValue,
+    item_id: i32,
+    run_at: DateTime<Local>,
+    created_at: DateTime<Local>,
+    updated_at: DateTime<Local>,
+}
+
+#[derive(QueryableByName)]
+struct ClaimedTaskRecord {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Integer)]
+    item_id: i32,
+    #[diesel(sql_type = Integer)]
+    attempts_num: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::repository::tests::connect;
+
+    // Every test queues tasks of a kind of its own, as claiming takes any task of the kind
+    fn delete_tasks(repo: &Repository, kind: TaskKind) {
+        diesel::sql_query("DELETE FROM tasks WHERE kind = $1")
+            .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+    }
+
+    #[derive(QueryableByName, Debug, PartialEq)]
+    struct TaskStateRecord {
+        #[diesel(sql_type = Text)]
+        status: String,
+        #[diesel(sql_type = Integer)]
+        attempts_num: i32,
+        #[diesel(sql_type = Nullable<Text>)]
+        worker: Option<String>,
+    }
+
+    fn find_task_state(repo: &Repository, id: i32) -> Option<(String, i32, Option<String>)> {
+        diesel::sql_query("SELECT status::text, attempts_num, worker FROM tasks WHERE id = $1")
+            .bind::<Integer, _>(id)
+            .get_result::<TaskStateRecord>(&mut repo.pool.get().unwrap())
+            .optional()
+            .unwrap()
+            .map(|r| (r.status, r.attempts_num, r.worker))
+    }
+
+    fn claim(repo: &Repository, kind: TaskKind, worker: &str, max_attempts_num: i32) -> Vec<Task> {
+        let stale_before = Local::now() - Duration::minutes(5);
+        repo.claim_tasks(kind, worker, stale_before, max_attempts_num, 10)
+            .unwrap()
+    }
+
+    #[test]
+    fn enqueues_tasks_once_per_item() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::SummarizeText;
+        delete_tasks(&repo, kind);
+        assert_eq!(repo.enqueue_tasks(kind, &[]).unwrap(), 0);
+        assert_eq!(repo.enqueue_tasks(kind, &[1_901_000_000, 1_901_000_001]).unwrap(), 2);
+        assert_eq!(
+            repo.enqueue_tasks(kind, &[1_901_000_000, 1_901_000_001, 1_901_000_002])
+                .unwrap(),
+            1
+        );
+        // Dead tasks are kept to be requeued explicitly rather than being enqueued again
+        diesel::sql_query("UPDATE tasks SET status = 'dead' WHERE kind = $1")
+            .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        assert_eq!(repo.enqueue_tasks(kind, &[1_901_000_000]).unwrap(), 0);
+        // Beyond the chunk of a statement
+        let item_ids: Vec<i32> = (1_901_000_000..1_901_002_500).collect();
+        assert_eq!(repo.enqueue_tasks(kind, &item_ids).unwrap(), 2497);
+    }
+
+    #[test]
+    fn claims_tasks_skipping_locked_ones() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::AnalyzeStoryText;
+        delete_tasks(&repo, kind);
+        repo.enqueue_tasks(kind, &[1_901_100_000, 1_901_100_001, 1_901_100_002])
+            .unwrap();
+        // A task locked by a claim in progress elsewhere is left to it
+        let claimed = repo
+            .pool
+            .get()
+            .unwrap()
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                diesel::sql_query("SELECT id FROM tasks WHERE kind = $1 AND item_id = $2 FOR UPDATE")
+                    .bind::<TaskKindType, _>(TaskKindValue::from(kind))
+                    .bind::<Integer, _>(1_901_100_000)
+                    .execute(conn)?;
+                Ok(claim(&repo, kind, "worker-a", 5))
+            })
+            .unwrap();
+        let mut item_ids = claimed.iter().map(|t| t.item_id).collect::<Vec<i32>>();
+        item_ids.sort();
+        assert_eq!(item_ids, [1_901_100_001, 1_901_100_002]);
+        assert!(claimed.iter().all(|t| t.attempts_num == 1));
+        // Running tasks which are kept alive aren't claimed again
+        let claimed = claim(&repo, kind, "worker-b", 5);
+        assert_eq!(claimed.iter().map(|t| t.item_id).collect::<Vec<i32>>(), [1_901_100_000]);
+        assert!(claim(&repo, kind, "worker-b", 5).is_empty());
+    }
+
+    #[test]
+    fn retries_failed_tasks_once_due() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::AnalyzeCommentText;
+        delete_tasks(&repo, kind);
+        repo.enqueue_tasks(kind, &[1_901_200_000]).unwrap();
+        let id = claim(&repo, kind, "worker-a", 5)[0].id;
+        assert!(repo
+            .fail_task(id, "worker-a", 5, Local::now() + Duration::hours(1), "Failed")
+            .unwrap());
+        assert_eq!(find_task_state(&repo, id), Some(("queued".to_string(), 1, None)));
+        assert!(claim(&repo, kind, "worker-a", 5).is_empty());
+        // The task isn't failed twice
+        assert!(!repo.fail_task(id, "worker-a", 5, Local::now(), "Failed").unwrap());
+        diesel::sql_query("UPDATE tasks SET run_at = NOW() WHERE id = $1")
+            .bind::<Integer, _>(id)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        let claimed = claim(&repo, kind, "worker-a", 5);
+        assert_eq!(
+            claimed.iter().map(|t| (t.id, t.attempts_num)).collect::<Vec<_>>(),
+            [(id, 2)]
+        );
+    }
+
+    #[test]
+    fn dead_letters_tasks_out_of_attempts() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::AnalyzeSummary;
+        delete_tasks(&repo, kind);
+        repo.enqueue_tasks(kind, &[1_901_300_000]).unwrap();
+        let mut id = 0;
+        for attempts_num in 1..=2 {
+            let claimed = claim(&repo, kind, "worker-a", 2);
+            assert_eq!(
+                claimed.iter().map(|t| t.attempts_num).collect::<Vec<i32>>(),
+                [attempts_num]
+            );
+            id = claimed[0].id;
+            assert!(repo.fail_task(id, "worker-a", 2, Local::now(), "Failed").unwrap());
+        }
+        assert_eq!(find_task_state(&repo, id), Some(("dead".to_string(), 2, None)));
+        assert!(claim(&repo, kind, "worker-a", 2).is_empty());
+        assert_eq!(repo.requeue_dead_tasks(Some(kind)).unwrap(), 1);
+        let claimed = claim(&repo, kind, "worker-a", 2);
+        assert_eq!(claimed.iter().map(|t| t.attempts_num).collect::<Vec<i32>>(), [1]);
+        assert_eq!(
+            find_task_state(&repo, claimed[0].id),
+            Some(("running".to_string(), 1, Some("worker-a".to_string())))
+        );
+    }
+
+    #[test]
+    fn finishes_only_tasks_held_by_the_worker() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::EmbedSummary;
+        delete_tasks(&repo, kind);
+        repo.enqueue_tasks(kind, &[1_901_400_000]).unwrap();
+        let id = claim(&repo, kind, "worker-a", 5)[0].id;
+        assert!(!repo.finish_task(id, "worker-b").unwrap());
+        assert!(!repo.fail_task(id, "worker-b", 5, Local::now(), "Failed").unwrap());
+        assert_eq!(
+            find_task_state(&repo, id),
+            Some(("running".to_string(), 1, Some("worker-a".to_string())))
+        );
+        assert!(repo.finish_task(id, "worker-a").unwrap());
+        assert_eq!(find_task_state(&repo, id), None);
+    }
+
+    #[test]
+    fn takes_over_stale_tasks() {
+        let Some(repo) = connect() else { return };
+        let kind = TaskKind::EmbedKeyword;
+        delete_tasks(&repo, kind);
+        repo.enqueue_tasks(kind, &[1_901_500_000, 1_901_500_001]).unwrap();
+        let claimed = claim(&repo, kind, "worker-a", 2);
+        assert_eq!(claimed.len(), 2);
+        let (id, exhausted_id) = (claimed[0].id, claimed[1].id);
+        diesel::sql_query("UPDATE tasks SET attempts_num = 2 WHERE id = $1")
+            .bind::<Integer, _>(exhausted_id)
+            .execute(&mut repo.pool.get().unwrap())
+            .unwrap();
+        // The worker stops sending heartbeats, so its tasks are stale to another worker
+        let stale_before = Local::now() + Duration::minutes(1);
+        let taken_over = repo.claim_tasks(kind, "worker-b", stale_before, 2, 10).unwrap();
+        assert_eq!(
+            taken_over.iter().map(|t| (t.id, t.attempts_num)).collect::<Vec<_>>(),
+            [(id, 2)]
+        );
+        assert_eq!(
+            find_task_state(&repo, exhausted_id),
+            Some(("dead".to_string(), 2, None))
+        );
+        // Once the worker comes back, it leaves the task to the other one
+        repo.heartbeat_tasks("worker-a").unwrap();
+        assert!(!repo.finish_task(id, "worker-a").unwrap());
+        assert!(!repo.fail_task(id, "worker-a", 2, Local::now(), "Failed").unwrap());
+        assert!(!repo
+            .fail_task(exhausted_id, "worker-a", 2, Local::now(), "Failed")
+            .unwrap());
+        assert_eq!(
+            find_task_state(&repo, id),
+            Some(("running".to_string(), 2, Some("worker-b".to_string())))
+        );
+        assert!(repo.finish_task(id, "worker-b").unwrap());
+        assert_eq!(find_task_state(&repo, id), None);
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use diesel::{prelude::*, sql_types::*, upsert::excluded};
+
+use super::Repository;
+use crate::{schema::users, service::User};
+
+impl Repository {
+    pub(crate) fn find_stale_users(&self, stale_before: DateTime<Local>, limit: usize) -> Result<Vec<String>> {
+        // Authors who have never been collected come first, then the least recently refreshed ones,
+        // where the deleted accounts come after the others as their profiles are not expected to come back
+        let stale_users = diesel::sql_query(
+            "SELECT s.by AS id \
+            FROM (SELECT DISTINCT by FROM items WHERE by IS NOT NULL) AS s(by) \
+            LEFT JOIN users ON s.by = users.id \
+            WHERE users.id IS NULL OR users.updated_at < $1 \
+            ORDER BY users.deleted ASC NULLS FIRST, users.updated_at ASC NULLS FIRST LIMIT $2",
+        )
+        .bind::<Timestamptz, _>(stale_before)
+        .bind::<BigInt, _>(limit as i64)
+        .get_results::<StaleUserRecord>(&mut self.pool.get()?)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+        return Ok(stale_users);
+    }
+
+    pub(crate) fn upsert_user(&self, user: User) -> Result<()> {
+        let user_record = InsertUserRecord {
+            id: user.id,
+            created: user.created,
+            karma: user.karma,
+            about: user.about,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            deleted: false,
+        };
+        diesel::insert_into(users::table)
+            .values(&user_record)
+            .on_conflict(users::id)
+            .do_update()
+            .set((
+                users::created.eq(excluded(users::created)),
+                users::karma.eq(excluded(users::karma)),
+                users::about.eq(excluded(users::about)),
+                users::updated_at.eq(excluded(users::updated_at)),
+                users::deleted.eq(excluded(users::deleted)),
+            ))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+
+    // Keeps the profile collected before the account was deleted, only recording when it was found deleted
+    pub(crate) fn upsert_deleted_user(&self, id: String) -> Result<()> {
+        let user_record = InsertUserRecord {
+            id,
+            created: None,
+            karma: None,
+            about: None,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            deleted: true,
+        };
+        diesel::insert_into(users::table)
+            .values(&user_record)
+            .on_conflict(users::id)
+            .do_update()
+            .set((
+                users::updated_at.eq(excluded(users::updated_at)),
+                users::deleted.eq(excluded(users::deleted)),
+            ))
+            .execute(&mut self.pool.get()?)?;
+        Ok(())
+    }
+}
+
+#[derive(QueryableByName)]
+struct StaleUserRecord {
+    #[diesel(sql_type = Text)]
+    id: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+struct InsertUserRecord {
+    id: String,
+    created: Option<i64>,
+    karma: Option<i32>,
+    about: Option<String>,
+    created_at: DateTime<Local>,
+    updated_at: DateTime<Local>,
+    deleted: bool,
+}
@@ -1,9 +1,29 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "fetch_strategy"))]
+    pub struct FetchStrategy;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "item_type"))]
     pub struct ItemType;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "item_url_status"))]
+    pub struct ItemUrlStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "story_list_type"))]
+    pub struct StoryListType;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "task_kind"))]
+    pub struct TaskKind;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "task_status"))]
+    pub struct TaskStatus;
 }
 
 diesel::table! {
@@ -14,19 +34,73 @@ diesel::table! {
         updated_at -> Timestamptz,
         text_passage -> Nullable<Text>,
         summary_passage -> Nullable<Text>,
+        keyword_prompts -> Nullable<Array<Nullable<Text>>>,
+        text_passage_prompts -> Nullable<Array<Nullable<Text>>>,
+        summary_passage_prompts -> Nullable<Array<Nullable<Text>>>,
+    }
+}
+
+diesel::table! {
+    blobs (hash) {
+        hash -> Text,
+        content -> Bytea,
+        compressed -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    checkpoints (name) {
+        name -> Text,
+        value -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    generations (id) {
+        id -> Int4,
+        item_id -> Int4,
+        prompt -> Text,
+        input_hash -> Text,
+        model -> Nullable<Text>,
+        temperature -> Nullable<Float4>,
+        output -> Nullable<Text>,
+        error -> Nullable<Text>,
+        duration_ms -> Int4,
+        created_at -> Timestamptz,
+        seed -> Nullable<Int8>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::FetchStrategy;
+    use super::sql_types::ItemUrlStatus;
+
     item_urls (item_id) {
         item_id -> Int4,
-        html -> Nullable<Text>,
         text -> Nullable<Text>,
         summary -> Nullable<Text>,
-        status_code -> Nullable<Int4>,
         status_note -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        fetch_strategy -> Nullable<FetchStrategy>,
+        title -> Nullable<Text>,
+        author -> Nullable<Text>,
+        published_at -> Nullable<Timestamptz>,
+        canonical_url -> Nullable<Text>,
+        status -> Nullable<ItemUrlStatus>,
+        attempts_num -> Int4,
+        next_attempt_at -> Nullable<Timestamptz>,
+        normalized_url -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        duplicate_of -> Nullable<Int4>,
+        html_hash -> Nullable<Text>,
+        topics -> Nullable<Array<Nullable<Text>>>,
+        summary_prompts -> Nullable<Array<Nullable<Text>>>,
+        summary_rejection -> Nullable<Text>,
     }
 }
 
@@ -51,14 +125,69 @@ diesel::table! {
         descendants -> Nullable<Int4>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        refreshed_at -> Nullable<Timestamptz>,
+        kids -> Nullable<Array<Nullable<Int4>>>,
+        parts -> Nullable<Array<Nullable<Int4>>>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::StoryListType;
+
+    story_lists (id) {
+        id -> Int4,
+        #[sql_name = "type"]
+        type_ -> StoryListType,
+        item_ids -> Array<Nullable<Int4>>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TaskKind;
+    use super::sql_types::TaskStatus;
+
+    tasks (id) {
+        id -> Int4,
+        kind -> TaskKind,
+        item_id -> Int4,
+        status -> TaskStatus,
+        attempts_num -> Int4,
+        run_at -> Timestamptz,
+        worker -> Nullable<Text>,
+        heartbeat_at -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Text,
+        created -> Nullable<Int8>,
+        karma -> Nullable<Int4>,
+        about -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted -> Bool,
     }
 }
 
 diesel::joinable!(analyses -> items (item_id));
+diesel::joinable!(generations -> items (item_id));
 diesel::joinable!(item_urls -> items (item_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     analyses,
+    blobs,
+    checkpoints,
+    generations,
     item_urls,
     items,
+    story_lists,
+    tasks,
+    users,
 );
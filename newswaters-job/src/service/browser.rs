@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use futures::StreamExt;
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::JoinHandle,
+};
+
+// Keeps up to `browsers_num` browsers alive and lends them out one page at a time,
+// so that fetching many URLs doesn't need to launch a new Chromium process for each of them.
+pub(crate) struct BrowserPool {
+    semaphore: Semaphore,
+    idle_browsers: Mutex<Vec<PooledBrowser>>,
+    max_pages_num: usize,
+    user_agent: String,
+}
+
+impl BrowserPool {
+    pub(crate) fn new(browsers_num: usize, max_pages_num: usize, user_agent: &str) -> Self {
+        Self {
+            semaphore: Semaphore::new(browsers_num),
+            idle_browsers: Mutex::new(vec![]),
+            max_pages_num,
+            user_agent: user_agent.to_string(),
+        }
+    }
+
+    pub(crate) async fn get_content(&self, url: &str) -> Result<String> {
+        let _permit = self.semaphore.acquire().await?;
+        let idle_browser = self.idle_browsers.lock().await.pop();
+        let mut browser = match idle_browser {
+            // The handler stops as soon as the connection to the browser is lost, e.g. when it crashed
+            Some(browser) if !browser.handle.is_finished() => browser,
+            Some(mut browser) => {
+                browser.close().await;
+                PooledBrowser::launch(&self.user_agent).await?
+            }
+            None => PooledBrowser::launch(&self.user_agent).await?,
+        };
+        let content = browser.get_content(url).await;
+        browser.pages_num += 1;
+        // Recycle the browser after too many pages to keep its memory usage in check, or when it has failed
+        if content.is_ok() && browser.pages_num < self.max_pages_num {
+            self.idle_browsers.lock().await.push(browser);
+        } else {
+            browser.close().await;
+        }
+        content
+    }
+
+    pub(crate) async fn close(&self) {
+        let mut idle_browsers = self.idle_browsers.lock().await;
+        for mut browser in idle_browsers.drain(..) {
+            browser.close().await;
+        }
+    }
+}
+
+struct PooledBrowser {
+    browser: Browser,
+    handle: JoinHandle<()>,
+    pages_num: usize,
+}
+
+impl PooledBrowser {
+    async fn launch(user_agent: &str) -> Result<Self> {
+        let config = match BrowserConfig::builder()
+            .incognito()
+            // https://github.com/puppeteer/puppeteer/issues/1825#issuecomment-651755428
+            .no_sandbox()
+            .arg("--disable-gpu")
+            .arg("--single-process")
+            .arg("--no-zygote")
+            .arg(format!("--user-agent={}", user_agent))
+            .build()
+        {
+            Ok(config) => config,
+            Err(e) => bail!(e),
+        };
+        let (browser, mut handler) = Browser::launch(config).await?;
+        let handle = tokio::spawn(async move {
+            while let Some(h) = handler.next().await {
+                if h.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            browser,
+            handle,
+            pages_num: 0,
+        })
+    }
+
+    async fn get_content(&self, url: &str) -> Result<String> {
+        let page = self.browser.new_page(url).await?;
+        let content = page.content().await;
+        page.close().await?;
+        Ok(content?)
+    }
+
+    async fn close(&mut self) {
+        if let Err(e) = self.browser.close().await {
+            println!("[ERR] browser.close: err={e}");
+            self.browser.kill().await;
+        }
+        let _ = self.browser.wait().await;
+        self.handle.abort();
+    }
+}
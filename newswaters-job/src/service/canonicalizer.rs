@@ -0,0 +1,99 @@
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+// Links to the same page are normalized into the same string regardless of the scheme, "www.", trailing slashes,
+// fragments and tracking parameters, e.g. "https://www.example.com/a/?utm_source=hn#top" into "example.com/a".
+pub(crate) fn normalize_url(url: &str) -> Option<String> {
+    let url = Url::parse(url.trim()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    let host = url.host_str()?.trim_end_matches('.');
+    let mut normalized_url = host.strip_prefix("www.").unwrap_or(host).to_string();
+    // Default ports are already omitted by `Url`
+    if let Some(port) = url.port() {
+        normalized_url.push_str(&format!(":{}", port));
+    }
+    normalized_url.push_str(url.path().trim_end_matches('/'));
+    let mut params = url
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter(|p| !is_tracking_param(p.split('=').next().unwrap_or("")))
+        .collect::<Vec<&str>>();
+    if !params.is_empty() {
+        params.sort();
+        normalized_url.push('?');
+        normalized_url.push_str(&params.join("&"));
+    }
+    Some(normalized_url)
+}
+
+// Texts only differing in whitespace have the same hash
+pub(crate) fn hash_content(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn is_tracking_param(key: &str) -> bool {
+    let key = key.to_lowercase();
+    key.starts_with("utm_") || TRACKING_PARAMS.contains(&key.as_str())
+}
+
+const TRACKING_PARAMS: [&str; 12] = [
+    "fbclid", "gclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "mkt_tok", "_hsenc", "_hsmi", "ref", "ref_src",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_tracking_params_and_fragments() {
+        assert_eq!(
+            normalize_url("https://www.example.com/a/?utm_source=hn&utm_Medium=x#top").as_deref(),
+            Some("example.com/a")
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a?id=1&fbclid=x&ref=hn").as_deref(),
+            Some("example.com/a?id=1")
+        );
+    }
+
+    #[test]
+    fn normalizes_hosts() {
+        for url in [
+            "http://example.com/a",
+            "https://WWW.Example.COM/a/",
+            "https://example.com./a",
+            "https://example.com:443/a",
+        ] {
+            assert_eq!(normalize_url(url).as_deref(), Some("example.com/a"), "{url}");
+        }
+        assert_eq!(
+            normalize_url("https://example.com:8080/a").as_deref(),
+            Some("example.com:8080/a")
+        );
+        assert_eq!(normalize_url("ftp://example.com/a"), None);
+        assert_eq!(normalize_url("example.com/a"), None);
+    }
+
+    #[test]
+    fn sorts_params() {
+        assert_eq!(
+            normalize_url("https://example.com/a?b=2&a=1"),
+            normalize_url("https://example.com/a?a=1&b=2")
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a?b=2&a=1").as_deref(),
+            Some("example.com/a?a=1&b=2")
+        );
+    }
+
+    #[test]
+    fn hashes_content_regardless_of_whitespace() {
+        assert_eq!(hash_content("A text\n\n  of words "), hash_content("A text of words"));
+        assert_ne!(hash_content("A text of words"), hash_content("A text of other words"));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use scraper::{ElementRef, Html, Selector};
+
+use super::ArticleMetadata;
+
+// A simplified take on Readability, see: https://github.com/mozilla/readability
+// Paragraphs give points to their parent and grandparent, and the highest-scoring node is taken as the article body.
+pub(crate) fn extract_article(html: &str) -> (Option<String>, ArticleMetadata) {
+    let document = Html::parse_document(html);
+    let metadata = ArticleMetadata {
+        title: find_meta(&document, &["meta[property='og:title']", "meta[name='twitter:title']"])
+            .or_else(|| find_text(&document, &["title", "h1"])),
+        author: find_meta(&document, &["meta[name='author']", "meta[property='article:author']"])
+            .or_else(|| find_text(&document, &["[rel='author']", "[itemprop='author']"])),
+        published_at: find_meta(
+            &document,
+            &[
+                "meta[property='article:published_time']",
+                "meta[itemprop='datePublished']",
+                "meta[name='date']",
+                "time[datetime]",
+            ],
+        )
+        .and_then(|d| parse_date(&d)),
+        canonical_url: find_attr(&document, "link[rel='canonical']", "href")
+            .or_else(|| find_meta(&document, &["meta[property='og:url']"])),
+    };
+    (find_body(&document), metadata)
+}
+
+fn find_body(document: &Html) -> Option<String> {
+    let min_words_count = 50;
+    let mut scores = HashMap::new();
+    for paragraph in document.select(&selector("p, pre, td")) {
+        if is_unlikely(&paragraph) {
+            continue;
+        }
+        let text = paragraph.text().collect::<String>();
+        let text = text.trim();
+        if text.len() < 25 {
+            continue;
+        }
+        let score = 1.0 + text.matches(",").count() as f64 + f64::min(text.len() as f64 / 100.0, 3.0);
+        let parent = match paragraph.parent().and_then(ElementRef::wrap) {
+            Some(parent) => parent,
+            None => continue,
+        };
+        scores.entry(parent.id()).or_insert((parent, weight(&parent))).1 += score;
+        if let Some(grandparent) = parent.parent().and_then(ElementRef::wrap) {
+            scores
+                .entry(grandparent.id())
+                .or_insert((grandparent, weight(&grandparent)))
+                .1 += score / 2.0;
+        }
+    }
+    let (candidate, _) = scores
+        .into_values()
+        .map(|(element, score)| (element, score * (1.0 - link_density(&element))))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    let blocks = selector("p, h1, h2, h3, h4, h5, h6, li, pre, blockquote");
+    let mut lines = vec![];
+    for block in candidate.select(&blocks) {
+        // Nested blocks are already included in the text of their outermost block
+        let is_nested = block
+            .ancestors()
+            .take_while(|a| a.id() != candidate.id())
+            .filter_map(ElementRef::wrap)
+            .any(|a| blocks.matches(&a));
+        if is_nested || is_unlikely(&block) {
+            continue;
+        }
+        let line = if block.value().name() == "pre" {
+            block.text().collect::<String>()
+        } else {
+            block
+                .text()
+                .collect::<Vec<&str>>()
+                .join(" ")
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        };
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    let body = lines.join("\n");
+    if body.split_whitespace().count() < min_words_count {
+        return None;
+    }
+    Some(body)
+}
+
+// Navigation, cookie banners, footers and the like are never part of the article,
+// whereas the header of an article, unlike the one of the page, holds its title and lede
+fn is_unlikely(element: &ElementRef) -> bool {
+    element.ancestors().filter_map(ElementRef::wrap).any(|a| {
+        let is_page_header = a.value().name() == "header"
+            && !a
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|e| e.value().name() == "article");
+        is_page_header
+            || matches!(
+                a.value().name(),
+                "nav" | "footer" | "aside" | "form" | "script" | "style" | "noscript"
+            )
+            || weight(&a) < 0.0
+    })
+}
+
+fn weight(element: &ElementRef) -> f64 {
+    let names = format!(
+        "{} {}",
+        element.value().attr("class").unwrap_or(""),
+        element.value().attr("id").unwrap_or("")
+    )
+    .to_lowercase();
+    let mut weight = 0.0;
+    if NEGATIVE_NAMES.iter().any(|n| names.contains(n)) {
+        weight -= 25.0;
+    }
+    if POSITIVE_NAMES.iter().any(|n| names.contains(n)) {
+        weight += 25.0;
+    }
+    weight
+}
+
+fn link_density(element: &ElementRef) -> f64 {
+    let text_len = element.text().map(str::len).sum::<usize>();
+    if text_len == 0 {
+        return 0.0;
+    }
+    let link_text_len = element
+        .select(&selector("a"))
+        .map(|a| a.text().map(str::len).sum::<usize>())
+        .sum::<usize>();
+    link_text_len as f64 / text_len as f64
+}
+
+fn find_meta(document: &Html, selectors: &[&str]) -> Option<String> {
+    selectors
+        .iter()
+        .find_map(|s| find_attr(document, s, "content").or_else(|| find_attr(document, s, "datetime")))
+}
+
+fn find_attr(document: &Html, selector_str: &str, attr: &str) -> Option<String> {
+    document
+        .select(&selector(selector_str))
+        .filter_map(|e| e.value().attr(attr))
+        .map(|v| v.trim().to_string())
+        .find(|v| !v.is_empty())
+}
+
+fn find_text(document: &Html, selectors: &[&str]) -> Option<String> {
+    selectors.iter().find_map(|s| {
+        document
+            .select(&selector(s))
+            .map(|e| e.text().collect::<Vec<&str>>().join(" ").trim().to_string())
+            .find(|t| !t.is_empty())
+    })
+}
+
+fn parse_date(date: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_rfc2822(date))
+        .map(|d| d.with_timezone(&Local))
+        .ok()
+}
+
+fn selector(selectors: &str) -> Selector {
+    // Selectors are all hard-coded, so they are known to be valid
+    Selector::parse(selectors).unwrap()
+}
+
+const POSITIVE_NAMES: [&str; 10] = [
+    "article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story",
+];
+
+const NEGATIVE_NAMES: [&str; 16] = [
+    "nav",
+    "menu",
+    "footer",
+    "sidebar",
+    "banner",
+    "cookie",
+    "consent",
+    "comment",
+    "share",
+    "social",
+    "related",
+    "promo",
+    "advert",
+    "subscribe",
+    "newsletter",
+    "popup",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_blog_post() {
+        let (body, metadata) = extract_article(include_str!("../../tests/fixtures/blog_post.html"));
+        let body = body.unwrap();
+        assert!(body.starts_with("Speeding up incremental builds with a shared cache\n"));
+        // The header of the article is kept, unlike the one of the page
+        assert!(body.contains("now finish three times faster"));
+        assert!(body.contains("Invalidating the cache"));
+        assert!(body.contains("cache-key = hash(sources, Cargo.lock, rustc --version)"));
+        for boilerplate in [
+            "Example Blog, notes",
+            "We use cookies",
+            "Great write-up",
+            "Subscribe",
+            "Copyright",
+        ] {
+            assert!(!body.contains(boilerplate), "{}", boilerplate);
+        }
+        assert_eq!(
+            metadata.title.as_deref(),
+            Some("Speeding up incremental builds with a shared cache")
+        );
+        assert_eq!(metadata.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            metadata.published_at.map(|d| d.timestamp()),
+            Some(
+                DateTime::parse_from_rfc3339("2024-03-05T09:30:00Z")
+                    .unwrap()
+                    .timestamp()
+            )
+        );
+        assert_eq!(
+            metadata.canonical_url.as_deref(),
+            Some("https://blog.example.com/posts/shared-build-cache")
+        );
+    }
+
+    #[test]
+    fn extracts_news_article() {
+        let (body, metadata) = extract_article(include_str!("../../tests/fixtures/news_article.html"));
+        let body = body.unwrap();
+        assert!(body.starts_with("The city council voted on Tuesday"));
+        assert_eq!(body.lines().count(), 3);
+        for boilerplate in ["cookies", "Parking fees", "Bridge repairs"] {
+            assert!(!body.contains(boilerplate), "{}", boilerplate);
+        }
+        assert_eq!(metadata.title.as_deref(), Some("City council approves new bike lanes"));
+        assert_eq!(metadata.author.as_deref(), Some("John Smith"));
+        assert_eq!(
+            metadata.published_at.map(|d| d.timestamp()),
+            Some(
+                DateTime::parse_from_rfc2822("Tue, 12 Mar 2024 14:00:00 +0000")
+                    .unwrap()
+                    .timestamp()
+            )
+        );
+        assert_eq!(
+            metadata.canonical_url.as_deref(),
+            Some("https://news.example.org/local/bike-lanes")
+        );
+    }
+
+    #[test]
+    fn finds_no_body_in_short_page() {
+        let (body, metadata) = extract_article(include_str!("../../tests/fixtures/short_page.html"));
+        assert!(body.is_none());
+        assert_eq!(metadata.title.as_deref(), Some("Sign in"));
+        assert!(metadata.author.is_none() && metadata.published_at.is_none() && metadata.canonical_url.is_none());
+    }
+}
@@ -1,81 +1,431 @@
+use std::env;
+
 use anyhow::{bail, Result};
-use chromiumoxide::browser::{Browser, BrowserConfig};
-use futures::StreamExt;
+use encoding_rs::{Encoding, UTF_8};
 use html2text::{self, render::text_renderer::TrivialDecorator};
-use reqwest::header::CONTENT_TYPE;
+use reqwest::{
+    header::{CONTENT_TYPE, LOCATION, RETRY_AFTER},
+    Response, Url,
+};
 
-use super::{Item, ItemUrl};
+use super::{
+    browser::BrowserPool,
+    extractor,
+    politeness::{Access, DomainPermit, Politeness},
+    ArticleMetadata, FetchStrategy, Item, ItemUrl, ItemUrlFailure, StoryList, Updates, User,
+};
 
 // See: https://github.com/HackerNews/API/tree/38154ba#max-item-id
 pub(crate) async fn get_max_item_id() -> Result<i32> {
-    let response = reqwest::get("https://hacker-news.firebaseio.com/v0/maxitem.json?print=pretty").await?;
+    let response = reqwest::get(endpoint("maxitem.json")).await?;
     Ok(response.text().await?.trim().parse()?)
 }
 
 // See: https://github.com/HackerNews/API/tree/38154ba#items
 pub(crate) async fn get_item(id: i32) -> Result<Item> {
-    let response = reqwest::get(format!(
-        "https://hacker-news.firebaseio.com/v0/item/{}.json?print=pretty",
-        id
-    ))
-    .await?;
+    let response = reqwest::get(endpoint(&format!("item/{}.json", id))).await?;
     let item = response.json::<Item>().await?;
     Ok(item)
 }
 
-pub(crate) async fn get_item_url(url: &str) -> Result<ItemUrl> {
-    let response = reqwest::get(url).await?;
-    let skipping_note = match response.headers().get(CONTENT_TYPE) {
-        Some(value) => match value.to_str() {
-            Ok(t) => {
-                if t.to_lowercase().contains("pdf") {
-                    Some(format!("Skipped: {}", t))
-                } else {
-                    None
-                }
+// See: https://github.com/HackerNews/API/tree/38154ba#users
+pub(crate) async fn get_user(id: &str) -> Result<Option<User>> {
+    let response = reqwest::get(endpoint(&format!("user/{}.json", id))).await?;
+    let user = response.json::<Option<User>>().await?;
+    Ok(user)
+}
+
+// See: https://github.com/HackerNews/API/tree/38154ba#changed-items-and-profiles
+pub(crate) async fn get_updates() -> Result<Updates> {
+    let response = reqwest::get(endpoint("updates.json")).await?;
+    let updates = response.json::<Updates>().await?;
+    Ok(updates)
+}
+
+// The limits and the rules of fetching pages, which are given by the command
+pub(crate) struct FetchOptions {
+    pub pdf_max_size: usize,
+    pub html_max_size: usize,
+    // Domains whose pages are always rendered by a browser, e.g. "twitter.com"
+    pub browser_domains: Vec<String>,
+    // Static pages with fewer words are taken as rendered on the client side
+    pub min_words_count: usize,
+    // Texts any shorter are not matched against other pages by their content
+    pub min_duplicate_text_len: usize,
+}
+
+pub(crate) async fn get_item_url(
+    url: &str,
+    browser_pool: &BrowserPool,
+    politeness: &Politeness,
+    options: &FetchOptions,
+) -> Result<ItemUrl> {
+    let mut request_url = Url::parse(url)?;
+    let mut redirects_num = 0;
+    // Redirects to other origins are followed here rather than by the client,
+    // so that the new host is throttled and its robots.txt is checked like the first one
+    let (permit, response) = loop {
+        let permit = match politeness.request_access(&request_url).await? {
+            Access::Allowed(permit) => permit,
+            Access::Disallowed => {
+                return Ok(ItemUrl::Failed {
+                    failure: ItemUrlFailure::Disallowed,
+                    note: format!("Disallowed by robots.txt ({})", request_url),
+                })
             }
-            Err(_) => None,
-        },
-        None => None,
-    };
-    if let Some(note) = skipping_note {
-        return Ok(ItemUrl::Skipped { note });
-    } else {
-        let config = match BrowserConfig::builder()
-            .incognito()
-            // https://github.com/puppeteer/puppeteer/issues/1825#issuecomment-651755428
-            .no_sandbox()
-            .arg("--disable-gpu")
-            .arg("--single-process")
-            .arg("--no-zygote")
-            .build()
-        {
-            Ok(config) => config,
-            Err(e) => bail!(e),
+            Access::Deferred(deferral) => {
+                return Ok(ItemUrl::Failed {
+                    failure: ItemUrlFailure::Deferred(deferral),
+                    note: format!("Deferred for {} secs by Retry-After", deferral.as_secs()),
+                })
+            }
+        };
+        let response = politeness.get(&permit, &request_url).await?;
+        let location = match response.headers().get(LOCATION) {
+            Some(location) if response.status().is_redirection() => location.to_str().ok(),
+            _ => None,
         };
-        let (mut browser, mut handler) = Browser::launch(config).await?;
-        let handle = tokio::spawn(async move {
-            while let Some(h) = handler.next().await {
-                if h.is_err() {
-                    break;
-                }
+        match location.map(|l| response.url().join(l)) {
+            Some(Ok(location)) if redirects_num < MAX_REDIRECTS_NUM => {
+                redirects_num += 1;
+                request_url = location;
             }
+            Some(Ok(_)) => {
+                return Ok(ItemUrl::Failed {
+                    failure: ItemUrlFailure::HttpError(Some(response.status().as_u16())),
+                    note: format!("Too many redirects ({})", redirects_num),
+                })
+            }
+            Some(Err(e)) => bail!(e),
+            None => break (permit, response),
+        }
+    };
+    // Rendered by a browser from where the redirects have led to
+    let url = response.url().to_string();
+    let url = url.as_str();
+    // Bot protections tend to turn away plain HTTP clients, while letting browsers through,
+    // unless the server has asked to come back later
+    if BROWSER_FALLBACK_STATUSES.contains(&response.status().as_u16()) && !response.headers().contains_key(RETRY_AFTER)
+    {
+        return get_browser_text(url, browser_pool, politeness, &permit).await;
+    }
+    if !response.status().is_success() {
+        return Ok(ItemUrl::Failed {
+            failure: ItemUrlFailure::HttpError(Some(response.status().as_u16())),
+            note: format!("HTTP status {}", response.status()),
+        });
+    }
+    let content_type = match response.headers().get(CONTENT_TYPE) {
+        Some(value) => value.to_str().unwrap_or("").to_lowercase(),
+        None => "".to_string(),
+    };
+    let mime_type = content_type.split(';').next().unwrap_or("").trim().to_string();
+    if mime_type.contains("pdf") {
+        return get_pdf_text(response, options.pdf_max_size).await;
+    }
+    // Servers leaving out the content type mostly serve HTML
+    if !mime_type.is_empty() && !HTML_MIME_TYPES.contains(&mime_type.as_str()) && mime_type != "text/plain" {
+        return Ok(ItemUrl::Failed {
+            failure: ItemUrlFailure::UnsupportedContent,
+            note: format!("Unsupported content type ({})", mime_type),
+        });
+    }
+    if mime_type == "text/plain" {
+        let max_size = options.html_max_size;
+        let text = match read_body(response, max_size).await? {
+            Some(bytes) => decode_text(&bytes, &content_type),
+            None => return Ok(oversize_item_url("Text", max_size)),
+        };
+        return Ok(ItemUrl::Finished {
+            html: None,
+            text,
+            metadata: ArticleMetadata::default(),
+            strategy: FetchStrategy::Http,
         });
-        let page = browser.new_page(url).await?;
-        let html = page.content().await?;
-        browser.close().await?;
-        handle.await?;
-        let text = match std::panic::catch_unwind(|| {
-            html2text::from_read_with_decorator(html.as_bytes(), std::usize::MAX, TrivialDecorator::new())
-        }) {
-            Ok(text) => text,
-            Err(_) => bail!("An error occurred in html2text::from_read_with_decorator"),
+    }
+    // Use the static HTML as is unless the page needs to be rendered by a browser
+    if !is_browser_domain(response.url(), &options.browser_domains) {
+        let max_size = options.html_max_size;
+        let html = match read_body(response, max_size).await? {
+            Some(bytes) => decode_text(&bytes, &content_type),
+            None => return Ok(oversize_item_url("HTML", max_size)),
         };
-        return Ok(ItemUrl::Finished { html, text });
+        let (text, metadata) = match extract_text(&html) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                return Ok(ItemUrl::Failed {
+                    failure: ItemUrlFailure::ExtractionFailure,
+                    note: e.to_string(),
+                })
+            }
+        };
+        if !is_js_rendered(&html, &text, options.min_words_count) {
+            return Ok(ItemUrl::Finished {
+                html: Some(html),
+                text,
+                metadata,
+                strategy: FetchStrategy::Http,
+            });
+        }
     }
+    get_browser_text(url, browser_pool, politeness, &permit).await
+}
+
+async fn get_browser_text(
+    url: &str,
+    browser_pool: &BrowserPool,
+    politeness: &Politeness,
+    permit: &DomainPermit,
+) -> Result<ItemUrl> {
+    politeness.wait_turn(permit).await;
+    let html = browser_pool.get_content(url).await?;
+    let (text, metadata) = match extract_text(&html) {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            return Ok(ItemUrl::Failed {
+                failure: ItemUrlFailure::ExtractionFailure,
+                note: e.to_string(),
+            })
+        }
+    };
+    return Ok(ItemUrl::Finished {
+        html: Some(html),
+        text,
+        metadata,
+        strategy: FetchStrategy::Browser,
+    });
+}
+
+async fn get_pdf_text(response: Response, max_size: usize) -> Result<ItemUrl> {
+    let bytes = match read_body(response, max_size).await? {
+        Some(bytes) => bytes,
+        None => return Ok(oversize_item_url("PDF", max_size)),
+    };
+    let text = match tokio::task::spawn_blocking(move || {
+        std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes))
+    })
+    .await?
+    {
+        Ok(Ok(text)) => text,
+        Ok(Err(e)) => {
+            return Ok(ItemUrl::Failed {
+                failure: ItemUrlFailure::ExtractionFailure,
+                note: format!("Unparseable PDF ({})", e),
+            })
+        }
+        Err(_) => {
+            return Ok(ItemUrl::Failed {
+                failure: ItemUrlFailure::ExtractionFailure,
+                note: "An error occurred in pdf_extract::extract_text_from_mem".to_string(),
+            })
+        }
+    };
+    // Scanned documents only consist of images
+    if text.trim().is_empty() {
+        return Ok(ItemUrl::Failed {
+            failure: ItemUrlFailure::ExtractionFailure,
+            note: "PDF contains no extractable text".to_string(),
+        });
+    }
+    Ok(ItemUrl::Finished {
+        html: None,
+        text,
+        metadata: ArticleMetadata::default(),
+        strategy: FetchStrategy::Pdf,
+    })
+}
+
+// Returns `None` once the body exceeds `max_size` bytes, where the declared length can be missing or wrong,
+// so the limit is also enforced while downloading
+async fn read_body(mut response: Response, max_size: usize) -> Result<Option<Vec<u8>>> {
+    if let Some(size) = response.content_length() {
+        if size as usize > max_size {
+            return Ok(None);
+        }
+    }
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > max_size {
+            return Ok(None);
+        }
+    }
+    Ok(Some(bytes))
+}
+
+fn oversize_item_url(kind: &str, max_size: usize) -> ItemUrl {
+    ItemUrl::Failed {
+        failure: ItemUrlFailure::UnsupportedContent,
+        note: format!("{} size exceeds the limit ({} bytes)", kind, max_size),
+    }
+}
+
+// Decodes the body by the charset of its content type like `Response::text`, which can't be capped in size
+fn decode_text(bytes: &[u8], content_type: &str) -> String {
+    let encoding = content_type
+        .split(';')
+        .filter_map(|p| p.trim().strip_prefix("charset="))
+        .find_map(|c| Encoding::for_label(c.trim_matches('"').as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
 }
 
 pub(crate) async fn get_top_story_ids() -> Result<Vec<i32>> {
-    let response = reqwest::get("https://hacker-news.firebaseio.com/v0/topstories.json?print=pretty").await?;
+    get_story_ids(StoryList::Top).await
+}
+
+pub(crate) async fn get_story_ids(list: StoryList) -> Result<Vec<i32>> {
+    let response = reqwest::get(endpoint(&format!("{}stories.json", list.name()))).await?;
     Ok(response.json().await?)
 }
+
+// `HACKER_NEWS_API_URL` allows pointing the job at a local server that imitates the Firebase endpoints
+fn endpoint(path: &str) -> String {
+    format!(
+        "{}/v0/{}?print=pretty",
+        env::var("HACKER_NEWS_API_URL").unwrap_or("https://hacker-news.firebaseio.com".to_string()),
+        path
+    )
+}
+
+// Prefers the main content of the page, and falls back to the whole page if no article body can be found
+pub(crate) fn extract_text(html: &str) -> Result<(String, ArticleMetadata)> {
+    let (body, metadata) = std::panic::catch_unwind(|| extractor::extract_article(html)).unwrap_or_default();
+    let text = match body {
+        Some(body) => body,
+        None => html_to_text(html)?,
+    };
+    Ok((text, metadata))
+}
+
+fn html_to_text(html: &str) -> Result<String> {
+    match std::panic::catch_unwind(|| {
+        html2text::from_read_with_decorator(html.as_bytes(), std::usize::MAX, TrivialDecorator::new())
+    }) {
+        Ok(text) => Ok(text),
+        Err(_) => bail!("An error occurred in html2text::from_read_with_decorator"),
+    }
+}
+
+// The subdomains of a browser domain are rendered by a browser too
+fn is_browser_domain(url: &Url, browser_domains: &[String]) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return false,
+    };
+    browser_domains
+        .iter()
+        .map(|d| d.trim().to_lowercase())
+        .filter(|d| !d.is_empty())
+        .any(|d| host == d || host.ends_with(&format!(".{}", d)))
+}
+
+// Pages built on the client side barely contain any text before their scripts are run
+fn is_js_rendered(html: &str, text: &str, min_words_count: usize) -> bool {
+    if text.split_whitespace().count() < min_words_count {
+        return true;
+    }
+    let html = html.to_lowercase();
+    SPA_MARKERS.iter().any(|m| html.contains(m))
+}
+
+const MAX_REDIRECTS_NUM: usize = 10;
+
+const BROWSER_FALLBACK_STATUSES: [u16; 3] = [401, 403, 503];
+
+const HTML_MIME_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+const SPA_MARKERS: [&str; 6] = [
+    "<div id=\"root\"></div>",
+    "<div id=\"app\"></div>",
+    "<div id=\"__next\"></div>",
+    "<app-root></app-root>",
+    "you need to enable javascript to run this app",
+    "please enable javascript to continue",
+];
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{
+        atomic::{AtomicI32, Ordering},
+        OnceLock,
+    };
+
+    use axum::{extract::Path, routing, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // The items served by the stand-in server, the last one being the max item, and the first one updated
+    // The item served without its type, so that it fails to be stored
+    pub(crate) static STAND_IN_FAILING_ID: AtomicI32 = AtomicI32::new(0);
+
+    pub(crate) const STAND_IN_ITEM_IDS: [i32; 5] = [
+        1_900_800_000,
+        1_900_800_001,
+        1_900_800_002,
+        1_900_800_003,
+        1_900_800_004,
+    ];
+
+    // Serves the Firebase endpoints on a local port, pointing `HACKER_NEWS_API_URL` at it. It runs on a thread of its own
+    // as it's shared by the tests, each of which has a runtime of its own
+    pub(crate) fn stand_in() {
+        static URL: OnceLock<String> = OnceLock::new();
+        URL.get_or_init(|| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let app = Router::new()
+                .route(
+                    "/v0/maxitem.json",
+                    routing::get(|| async { Json(STAND_IN_ITEM_IDS[4]) }),
+                )
+                .route("/v0/item/:name", routing::get(item))
+                .route(
+                    "/v0/updates.json",
+                    routing::get(|| async { Json(json!({"items": [STAND_IN_ITEM_IDS[0]], "profiles": []})) }),
+                );
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Runtime::new().unwrap();
+                runtime.block_on(async move {
+                    let server = axum::Server::from_tcp(listener).unwrap();
+                    server.serve(app.into_make_service()).await
+                })
+            });
+            env::set_var("HACKER_NEWS_API_URL", &url);
+            url
+        });
+    }
+
+    async fn item(Path(name): Path<String>) -> Json<Value> {
+        let id: i32 = name.trim_end_matches(".json").parse().unwrap();
+        if !STAND_IN_ITEM_IDS.contains(&id) {
+            return Json(Value::Null);
+        }
+        if id == STAND_IN_FAILING_ID.load(Ordering::SeqCst) {
+            return Json(json!({"id": id}));
+        }
+        Json(json!({
+            "id": id,
+            "type": "story",
+            "by": "pg",
+            "time": 1700000000,
+            "title": format!("Story {id}"),
+            "url": format!("https://example.com/{id}"),
+            "score": 1,
+        }))
+    }
+
+    #[tokio::test]
+    async fn gets_items_and_updates() {
+        stand_in();
+        assert_eq!(get_max_item_id().await.unwrap(), STAND_IN_ITEM_IDS[4]);
+        let item = get_item(STAND_IN_ITEM_IDS[1]).await.unwrap();
+        assert_eq!(item.id, STAND_IN_ITEM_IDS[1]);
+        assert_eq!(item.title.as_deref(), Some("Story 1900800001"));
+        let updates = get_updates().await.unwrap();
+        assert_eq!(updates.items, [STAND_IN_ITEM_IDS[0]]);
+        assert!(updates.profiles.is_empty());
+    }
+}
@@ -1,161 +1,332 @@
-use std::{env, time::Duration};
+use std::{
+    env,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    prompt::{self, Template},
+    validator::{self, SummaryChecks},
+    Generation, Summary, SummaryRejection,
+};
 
 #[derive(Serialize)]
 struct InstructRequest {
     instruction: String,
+    seed: u32,
 }
 
 #[derive(Deserialize)]
 struct InstructResponse {
     completion: String,
+    // Missing from the responses of older inference servers
+    model: Option<String>,
+    temperature: Option<f32>,
 }
 
-pub(crate) async fn instruct_summary(title: &str, text: &str) -> Result<String> {
-    let instruction = format!(
-        "\
-        Please generate related topics and provide a detailed summary that aligns with the title and omits any irrelevant text. \
-        Don't output the title. \
-        Don't make up information if it's not provided.\n\n\
-        Title:\n\
-        {}\n\n\
-        Content:\n\
-        {}\n\n\
-        Output format:\n\
-        - Topics:\n\
-        - Summary:\n\
-        ",
-        title, text
-    );
-    let summary = instruct(instruction).await?;
+// The prompts behind each kind of generated text, whose current keys are recorded along with it
+pub(crate) const SUMMARY_PROMPTS: [&str; 2] = ["summary", "merged_summary"];
+pub(crate) const KEYWORD_PROMPTS: [&str; 1] = ["keyword"];
+pub(crate) const TEXT_PASSAGE_PROMPTS: [&str; 4] = [
+    "comment_anchor_passage",
+    "entailment_passage",
+    "contradiction_passage",
+    "random_passage",
+];
+pub(crate) const SUMMARY_PASSAGE_PROMPTS: [&str; 5] = [
+    "summary_anchor_passage",
+    "entailment_passage",
+    "contradiction_passage",
+    "random_passage",
+    "subject_passage",
+];
+
+pub(crate) async fn instruct_summary(
+    prompts_dir: &Path,
+    title: &str,
+    text: &str,
+    checks: &SummaryChecks,
+    max_attempts_num: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<Summary> {
+    let summary = instruct_structured_summary(
+        prompts_dir,
+        "summary",
+        &[("title", title), ("text", text)],
+        text,
+        checks,
+        max_attempts_num,
+        generations,
+    )
+    .await?;
     return Ok(summary);
 }
 
-pub(crate) async fn instruct_keyword(title: &str, text: &str) -> Result<String> {
-    let instruction = format!(
-        "\
-        Please generate related keywords that align with the title and omits any irrelevant text. \
-        Output only the keywords without any additional explanation. \
-        The keywords should be separated by commas. \
-        Don't make up information if it's not provided.\n\n\
-        Title:\n\
-        {}\n\n\
-        Content:\n\
-        {}\n\n\
-        ",
-        title, text
-    );
-    let summary = instruct(instruction).await?;
+// Merges the summaries of the parts of a text which was too long to be summarized at once
+pub(crate) async fn instruct_merged_summary(
+    prompts_dir: &Path,
+    title: &str,
+    summaries: &[Summary],
+    checks: &SummaryChecks,
+    max_attempts_num: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<Summary> {
+    let summaries = summaries
+        .iter()
+        .map(|s| format!("- Topics: {}\n- Summary: {}", s.topics.join(", "), s.summary))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    let summary = instruct_structured_summary(
+        prompts_dir,
+        "merged_summary",
+        &[("title", title), ("summaries", &summaries)],
+        &summaries,
+        checks,
+        max_attempts_num,
+        generations,
+    )
+    .await?;
     return Ok(summary);
 }
 
-pub(crate) async fn instruct_summary_anchor_passage(summary: &str) -> Result<String> {
-    let instruction = format!(
-        "\
-        Please generate a sentence aligning with the provided content, omitting irrelevant text. \
-        Output the sentence without additional explanation. \
-        Ensure it is fewer than {} words.\n\n\
-        Content:\n\
-        {}\n\n\
-        ",
-        env::var("JOB_INSTRUCT_SUMMARY_ANCHOR_PASSAGE_MAX_WORDS_COUNT").unwrap_or("40".to_string()),
-        summary
-    );
-    let passage = instruct(instruction).await?;
+// Completions occasionally miss a section, wrap the output in explanations or fail the checks against the `source`,
+// so they are re-prompted, each time with another seed, up to a limit. Only a summary which has failed the checks is
+// rejected, whereas a completion which can't be parsed is an error like any other, so that the text is tried again.
+async fn instruct_structured_summary(
+    prompts_dir: &Path,
+    id: &str,
+    variables: &[(&str, &str)],
+    source: &str,
+    checks: &SummaryChecks,
+    max_attempts_num: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<Summary> {
+    let template = prompt::find_template(prompts_dir, id)?;
+    let mut attempts_num = 0;
+    loop {
+        attempts_num += 1;
+        let completion = instruct_template(&template, variables, generations).await?;
+        let summary = parse_summary(&completion).and_then(|summary| {
+            match validator::check_summary(&summary, source, &template, checks) {
+                Ok(_) => Ok(summary),
+                Err(e) => Err(SummaryRejection { reason: e.to_string() }.into()),
+            }
+        });
+        // The call itself has succeeded, but its output is of no use
+        if let (Err(e), Some(generation)) = (&summary, generations.last_mut()) {
+            generation.error = Some(e.to_string());
+        }
+        match summary {
+            Ok(summary) => return Ok(summary),
+            Err(e) if attempts_num < max_attempts_num => {
+                println!("[ERR] inference.instruct_structured_summary (attempts_num={attempts_num}): err={e}");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Expects the "Output format" of the summary instructions, e.g.
+// "- Topics: Rust, Compilers\n- Summary: ...", where topics may also be listed one per line, and the section names may be
+// emphasized in Markdown, e.g. "- **Topics:** Rust, Compilers".
+fn parse_summary(completion: &str) -> Result<Summary> {
+    let (head, summary) = match completion.split_once("Summary:") {
+        Some(sections) => sections,
+        None => bail!("Completion has no summary section"),
+    };
+    let topics = match head.split_once("Topics:") {
+        Some((_, topics)) => topics,
+        None => bail!("Completion has no topics section"),
+    };
+    let topics = topics
+        .split([',', '\n'])
+        .map(|t| t.trim_matches(is_list_marker))
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect::<Vec<String>>();
+    if topics.is_empty() {
+        bail!("Completion has no topics");
+    }
+    let summary = summary.trim_start_matches(is_list_marker).trim().to_string();
+    if summary.is_empty() {
+        bail!("Completion has no summary");
+    }
+    Ok(Summary { topics, summary })
+}
+
+// The bullets and the emphasis around the sections and the topics
+fn is_list_marker(c: char) -> bool {
+    c == '-' || c == '*' || c.is_whitespace()
+}
+
+pub(crate) async fn instruct_keyword(
+    prompts_dir: &Path,
+    title: &str,
+    text: &str,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let keyword = instruct_prompt(prompts_dir, "keyword", &[("title", title), ("text", text)], generations).await?;
+    return Ok(keyword);
+}
+
+pub(crate) async fn instruct_summary_anchor_passage(
+    prompts_dir: &Path,
+    summary: &str,
+    max_words_count: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let passage = instruct_prompt(
+        prompts_dir,
+        "summary_anchor_passage",
+        &[("max_words_count", &max_words_count.to_string()), ("summary", summary)],
+        generations,
+    )
+    .await?;
     return Ok(passage);
 }
 
-pub(crate) async fn instruct_comment_anchor_passage(comment: &str) -> Result<String> {
-    let instruction = format!(
-        "\
-        Given the following content:\n\
-        ## Content:\n\
-        {}\n\n\
-        ## Instruct: \
-        Pretend you are the speaker. Express it more succinctly in {} words or fewer. \
-        Remove any irrelevant text.\n\
-        ",
-        comment,
-        env::var("JOB_INSTRUCT_COMMENT_ANCHOR_PASSAGE_MAX_WORDS_COUNT").unwrap_or("40".to_string())
-    );
-    let passage = instruct(instruction).await?;
+pub(crate) async fn instruct_comment_anchor_passage(
+    prompts_dir: &Path,
+    comment: &str,
+    max_words_count: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let passage = instruct_prompt(
+        prompts_dir,
+        "comment_anchor_passage",
+        &[("comment", comment), ("max_words_count", &max_words_count.to_string())],
+        generations,
+    )
+    .await?;
     return Ok(passage);
 }
 
-pub(crate) async fn instruct_entailment_passage(premise: &str) -> Result<String> {
-    let instruction = format!(
-        "Refine the following sentence while keeping its meaning unchanged. \
-        Output the sentence without additional explanation.\n\n\
-        \"{}\"\n\
-        ",
-        premise
-    );
-    let hypothesis = instruct(instruction).await?;
+pub(crate) async fn instruct_entailment_passage(
+    prompts_dir: &Path,
+    premise: &str,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let hypothesis = instruct_prompt(prompts_dir, "entailment_passage", &[("premise", premise)], generations).await?;
     return Ok(hypothesis);
 }
 
-pub(crate) async fn instruct_contradiction_passage(premise: &str) -> Result<String> {
-    let instruction = format!(
-        "Make modifications to the following sentence, ensuring that its meaning becomes entirely contradictory. \
-        Output the sentence without additional explanation.\n\n\
-        \"{}\"\n\
-        ",
-        premise
-    );
-    let hypothesis = instruct(instruction).await?;
+pub(crate) async fn instruct_contradiction_passage(
+    prompts_dir: &Path,
+    premise: &str,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let hypothesis = instruct_prompt(
+        prompts_dir,
+        "contradiction_passage",
+        &[("premise", premise)],
+        generations,
+    )
+    .await?;
     return Ok(hypothesis);
 }
 
-pub(crate) async fn instruct_random_passage(original: &str) -> Result<String> {
+pub(crate) async fn instruct_random_passage(
+    prompts_dir: &Path,
+    original: &str,
+    words_retention_rate: f32,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
     let mut words = original
         .split(" ")
         .map(|n| n.to_string().to_lowercase())
         .collect::<Vec<String>>();
     let sentence_len = words.len();
     words.shuffle(&mut rand::thread_rng());
-    words.truncate(
-        (sentence_len as f32
-            * env::var("JOB_INSTRUCT_RANDOM_PASSAGE_WORDS_RETENTION_RATE")
-                .unwrap_or("0.1".to_string())
-                .parse::<f32>()?) as usize
-            + 1,
-    );
-    let instruction = format!(
-        "Generate a random sentence using the provided words. \
-        Ensure the sentence contains a minimum of {} words. \
-        Output the sentence without additional explanation.\n\n\
-        \"{}\"\n\
-        ",
-        sentence_len,
-        words.join(", ")
-    );
-    let hypothesis = instruct(instruction).await?;
+    words.truncate((sentence_len as f32 * words_retention_rate) as usize + 1);
+    let hypothesis = instruct_prompt(
+        prompts_dir,
+        "random_passage",
+        &[
+            ("min_words_count", &sentence_len.to_string()),
+            ("words", &words.join(", ")),
+        ],
+        generations,
+    )
+    .await?;
     return Ok(hypothesis);
 }
 
-pub(crate) async fn instruct_subject_passage(content: &str) -> Result<String> {
-    let instruction = format!(
-        "\
-        Please generate {} different subjects aligning with the content. \
-        Output subjects without additional explanation. \
-        Output each subject on a separate line. \
-        Each subject must consist of fewer than {} words.\n\n\
-        Content:\n\
-        {}\n\n\
-        ",
-        env::var("JOB_INSTRUCT_SUBJECT_PASSAGE_MAX_SUBJECTS_NUM").unwrap_or("5".to_string()),
-        env::var("JOB_INSTRUCT_SUBJECT_PASSAGE_MAX_WORDS_COUNT").unwrap_or("5".to_string()),
-        content
-    );
-    let subject = instruct(instruction).await?;
+pub(crate) async fn instruct_subject_passage(
+    prompts_dir: &Path,
+    content: &str,
+    max_subjects_num: usize,
+    max_words_count: usize,
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let subject = instruct_prompt(
+        prompts_dir,
+        "subject_passage",
+        &[
+            ("max_subjects_num", &max_subjects_num.to_string()),
+            ("max_words_count", &max_words_count.to_string()),
+            ("content", content),
+        ],
+        generations,
+    )
+    .await?;
     return Ok(subject);
 }
 
-async fn instruct(instruction: String) -> Result<String> {
-    let payload = InstructRequest { instruction };
+async fn instruct_prompt(
+    prompts_dir: &Path,
+    id: &str,
+    variables: &[(&str, &str)],
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let template = prompt::find_template(prompts_dir, id)?;
+    instruct_template(&template, variables, generations).await
+}
+
+// Records the call to the inference server in `generations`, whether it has succeeded or not.
+// Every call gets a random seed of its own, which is recorded to reproduce the completion.
+async fn instruct_template(
+    template: &Template,
+    variables: &[(&str, &str)],
+    generations: &mut Vec<Generation>,
+) -> Result<String> {
+    let instruction = template.render(variables)?;
+    let input_hash = format!("{:x}", Sha256::digest(instruction.as_bytes()));
+    let seed = rand::random::<u32>();
+    let start_time = Instant::now();
+    let response = instruct(instruction, seed).await;
+    let generation = match &response {
+        Ok(response) => Generation {
+            prompt: template.key(),
+            input_hash,
+            seed,
+            model: response.model.clone(),
+            temperature: response.temperature,
+            output: Some(response.completion.clone()),
+            error: None,
+            duration: start_time.elapsed(),
+        },
+        Err(e) => Generation {
+            prompt: template.key(),
+            input_hash,
+            seed,
+            model: None,
+            temperature: None,
+            output: None,
+            error: Some(e.to_string()),
+            duration: start_time.elapsed(),
+        },
+    };
+    generations.push(generation);
+    Ok(response?.completion)
+}
+
+async fn instruct(instruction: String, seed: u32) -> Result<InstructResponse> {
+    let payload = InstructRequest { instruction, seed };
     let client = reqwest::Client::new();
     let endpoint = format!(
         "http://{}:{}/instruct",
@@ -170,7 +341,23 @@ async fn instruct(instruction: String) -> Result<String> {
         .await?
         .json::<InstructResponse>()
         .await?;
-    return Ok(response.completion);
+    return Ok(response);
+}
+
+#[derive(Deserialize)]
+struct ContextResponse {
+    ctx_size: usize,
+}
+
+// The number of tokens shared by the prompt and the completion
+pub(crate) async fn get_context_size() -> Result<usize> {
+    let endpoint = format!(
+        "http://{}:{}/context",
+        env::var("INFERENCE_HOST")?,
+        env::var("INFERENCE_PORT")?
+    );
+    let response = reqwest::get(endpoint).await?.json::<ContextResponse>().await?;
+    Ok(response.ctx_size)
 }
 
 #[derive(Serialize)]
@@ -201,3 +388,37 @@ pub(crate) async fn embed(sentence: String) -> Result<Vec<f32>> {
     let embedding = response.embedding;
     Ok(embedding)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_summary() {
+        let summary = parse_summary("- Topics: Rust, Compilers\n- Summary: Rust builds are faster.").unwrap();
+        assert_eq!(summary.topics, vec!["Rust", "Compilers"]);
+        assert_eq!(summary.summary, "Rust builds are faster.");
+    }
+
+    #[test]
+    fn parses_summary_with_topics_per_line() {
+        let summary = parse_summary("Topics:\n- Rust\n- Compilers\n\nSummary:\nRust builds are faster.").unwrap();
+        assert_eq!(summary.topics, vec!["Rust", "Compilers"]);
+        assert_eq!(summary.summary, "Rust builds are faster.");
+    }
+
+    #[test]
+    fn parses_summary_in_markdown() {
+        let summary = parse_summary("- **Topics:** Rust, Compilers\n- **Summary:** Rust builds are faster.").unwrap();
+        assert_eq!(summary.topics, vec!["Rust", "Compilers"]);
+        assert_eq!(summary.summary, "Rust builds are faster.");
+    }
+
+    #[test]
+    fn rejects_summary_without_sections() {
+        assert!(parse_summary("Rust builds are faster.").is_err());
+        assert!(parse_summary("- Summary: Rust builds are faster.").is_err());
+        assert!(parse_summary("- **Topics:**\n- **Summary:** Rust builds are faster.").is_err());
+        assert!(parse_summary("- Topics: Rust\n- Summary: **").is_err());
+    }
+}
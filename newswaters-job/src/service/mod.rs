@@ -1,7 +1,19 @@
+pub(crate) mod browser;
+pub(crate) mod canonicalizer;
+pub(crate) mod extractor;
 pub(crate) mod hacker_news;
 pub(crate) mod inference;
+pub(crate) mod politeness;
+pub(crate) mod prompt;
 pub(crate) mod search_engine;
+pub(crate) mod validator;
 
+use std::{fmt, time::Duration};
+
+use anyhow::Error;
+use chromiumoxide::error::CdpError;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -16,20 +28,171 @@ pub(crate) struct Item {
     pub dead: Option<bool>,
     pub parent: Option<i32>,
     pub poll: Option<i32>,
-    #[allow(dead_code)]
     pub kids: Option<Vec<i32>>,
     pub url: Option<String>,
     pub score: Option<i32>,
     pub title: Option<String>,
-    #[allow(dead_code)]
     pub parts: Option<Vec<i32>>,
     pub descendants: Option<i32>,
 }
 
+#[derive(Deserialize)]
+pub(crate) struct User {
+    pub id: String,
+    pub created: Option<i64>,
+    pub karma: Option<i32>,
+    pub about: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Updates {
+    pub items: Vec<i32>,
+    pub profiles: Vec<String>,
+}
+
+// See: https://github.com/HackerNews/API/tree/38154ba#new-top-and-best-stories
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum StoryList {
+    Top,
+    New,
+    Best,
+    Ask,
+    Show,
+    Job,
+}
+
+impl StoryList {
+    pub(crate) const ALL: [StoryList; 6] = [
+        StoryList::Top,
+        StoryList::New,
+        StoryList::Best,
+        StoryList::Ask,
+        StoryList::Show,
+        StoryList::Job,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            StoryList::Top => "top",
+            StoryList::New => "new",
+            StoryList::Best => "best",
+            StoryList::Ask => "ask",
+            StoryList::Show => "show",
+            StoryList::Job => "job",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum FetchStrategy {
+    Http,
+    Browser,
+    Pdf,
+}
+
+#[derive(Default)]
+pub(crate) struct ArticleMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub published_at: Option<DateTime<Local>>,
+    pub canonical_url: Option<String>,
+}
+
 pub(crate) enum ItemUrl {
-    Finished { html: String, text: String },
-    Skipped { note: String },
-    Canceled { note: String },
+    Finished {
+        html: Option<String>,
+        text: String,
+        metadata: ArticleMetadata,
+        strategy: FetchStrategy,
+    },
+    Failed {
+        failure: ItemUrlFailure,
+        note: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ItemUrlFailure {
+    // Permanent, i.e. retrying won't help
+    UnsupportedContent,
+    ExtractionFailure,
+    // robots.txt forbids fetching the URL
+    Disallowed,
+    // The domain has asked to back off for this long
+    Deferred(Duration),
+    // Transient, except for the client errors other than timeouts and rate limits, e.g. "404 Not Found"
+    HttpError(Option<u16>),
+    Timeout,
+    DnsFailure,
+    ConnectionFailure,
+    UnknownFailure,
+}
+
+impl ItemUrlFailure {
+    pub(crate) fn from_error(error: &Error) -> Self {
+        if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() {
+                return ItemUrlFailure::Timeout;
+            }
+            if e.is_status() {
+                return ItemUrlFailure::HttpError(e.status().map(|s| s.as_u16()));
+            }
+            if e.is_connect() {
+                // reqwest doesn't expose the kind of connection errors, so the message is the only clue
+                let message = format!("{:?}", e).to_lowercase();
+                if message.contains("dns error") || message.contains("failed to lookup address") {
+                    return ItemUrlFailure::DnsFailure;
+                }
+                return ItemUrlFailure::ConnectionFailure;
+            }
+        }
+        if let Some(CdpError::Timeout) = error.downcast_ref::<CdpError>() {
+            return ItemUrlFailure::Timeout;
+        }
+        ItemUrlFailure::UnknownFailure
+    }
+
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            ItemUrlFailure::UnsupportedContent | ItemUrlFailure::ExtractionFailure | ItemUrlFailure::Disallowed => {
+                false
+            }
+            ItemUrlFailure::HttpError(Some(status)) => !(400..500).contains(status) || *status == 408 || *status == 429,
+            _ => true,
+        }
+    }
+}
+
+// Parsed from the "- Topics:" and "- Summary:" sections of a completion
+pub(crate) struct Summary {
+    pub topics: Vec<String>,
+    pub summary: String,
+}
+
+// A summary which has kept failing the checks, so that it's recorded as rejected rather than tried again
+#[derive(Debug)]
+pub(crate) struct SummaryRejection {
+    pub reason: String,
+}
+
+impl fmt::Display for SummaryRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Summary is rejected: {}", self.reason)
+    }
+}
+
+impl std::error::Error for SummaryRejection {}
+
+// A call to the inference server, which is recorded whether it has succeeded or not
+pub(crate) struct Generation {
+    pub prompt: String,
+    pub input_hash: String,
+    pub seed: u32,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub duration: Duration,
 }
 
 #[derive(Deserialize)]
@@ -38,4 +201,55 @@ pub(crate) struct Analysis {
     pub keyword: Option<String>,
     pub text_passage: Option<String>,
     pub summary_passage: Option<String>,
+    // The keys of the prompts the generated fields were generated by
+    pub prompts: Vec<String>,
+}
+
+// Each kind of task works on a single item, which is identified by its id
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum TaskKind {
+    CollectItem,
+    FetchItemUrl,
+    SummarizeText,
+    AnalyzeStoryText,
+    AnalyzeCommentText,
+    AnalyzeSummary,
+    EmbedSummary,
+    EmbedKeyword,
+}
+
+impl TaskKind {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TaskKind::CollectItem => "collect_item",
+            TaskKind::FetchItemUrl => "fetch_item_url",
+            TaskKind::SummarizeText => "summarize_text",
+            TaskKind::AnalyzeStoryText => "analyze_story_text",
+            TaskKind::AnalyzeCommentText => "analyze_comment_text",
+            TaskKind::AnalyzeSummary => "analyze_summary",
+            TaskKind::EmbedSummary => "embed_summary",
+            TaskKind::EmbedKeyword => "embed_keyword",
+        }
+    }
+}
+
+pub(crate) struct Task {
+    pub id: i32,
+    pub item_id: i32,
+    pub attempts_num: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_http_errors() {
+        assert!(!ItemUrlFailure::HttpError(Some(404)).is_transient());
+        assert!(!ItemUrlFailure::HttpError(Some(410)).is_transient());
+        assert!(ItemUrlFailure::HttpError(Some(408)).is_transient());
+        assert!(ItemUrlFailure::HttpError(Some(429)).is_transient());
+        assert!(ItemUrlFailure::HttpError(Some(500)).is_transient());
+        assert!(ItemUrlFailure::HttpError(None).is_transient());
+    }
 }
@@ -0,0 +1,506 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use chrono::DateTime;
+use reqwest::{header::RETRY_AFTER, redirect, Client, Response, StatusCode, Url};
+use tokio::sync::{Mutex, OnceCell, OwnedSemaphorePermit, Semaphore};
+
+// Requests are throttled per registered domain rather than per host,
+// since e.g. "gist.github.com" and "github.com" are served by the same people.
+pub(crate) struct Politeness {
+    client: Client,
+    user_agent: String,
+    permits_num: usize,
+    min_interval: Duration,
+    domains: Mutex<HashMap<String, Arc<Domain>>>,
+    robots: Mutex<HashMap<String, Arc<OnceCell<Robots>>>>,
+}
+
+pub(crate) enum Access {
+    Allowed(DomainPermit),
+    Disallowed,
+    // The domain has asked to back off with `Retry-After` for longer than `MAX_WAIT_SECS`
+    Deferred(Duration),
+}
+
+pub(crate) struct DomainPermit {
+    domain: Arc<Domain>,
+    interval: Duration,
+    _permit: OwnedSemaphorePermit,
+}
+
+struct Domain {
+    semaphore: Arc<Semaphore>,
+    next_request_at: Mutex<Instant>,
+}
+
+impl Politeness {
+    pub(crate) fn new(user_agent: &str, permits_num: usize, requests_per_sec: f64) -> Result<Self> {
+        if requests_per_sec <= 0.0 {
+            bail!("requests_per_sec must be positive: {}", requests_per_sec);
+        }
+        Ok(Self {
+            client: Client::builder()
+                .user_agent(user_agent)
+                .redirect(redirect::Policy::custom(|attempt| {
+                    // Stopped at the first hop to another origin, which is then requested on its own
+                    match attempt.previous().last() {
+                        Some(previous_url) if previous_url.origin() != attempt.url().origin() => attempt.stop(),
+                        _ if attempt.previous().len() > MAX_REDIRECTS_NUM => attempt.error("Too many redirects"),
+                        _ => attempt.follow(),
+                    }
+                }))
+                .build()?,
+            user_agent: user_agent.to_string(),
+            permits_num,
+            min_interval: Duration::from_secs_f64(1.0 / requests_per_sec),
+            domains: Mutex::new(HashMap::new()),
+            robots: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Waits until the domain of the URL has a free slot, and checks its robots.txt in the meantime
+    pub(crate) async fn request_access(&self, url: &Url) -> Result<Access> {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => bail!("URL has no host: {}", url),
+        };
+        let domain = Arc::clone(
+            self.domains
+                .lock()
+                .await
+                .entry(registered_domain(&host))
+                .or_insert_with(|| {
+                    Arc::new(Domain {
+                        semaphore: Arc::new(Semaphore::new(self.permits_num)),
+                        next_request_at: Mutex::new(Instant::now()),
+                    })
+                }),
+        );
+        let permit = Arc::clone(&domain.semaphore).acquire_owned().await?;
+        let robots_cell = Arc::clone(
+            self.robots
+                .lock()
+                .await
+                .entry(url.origin().ascii_serialization())
+                .or_default(),
+        );
+        let robots = robots_cell.get_or_try_init(|| self.get_robots(url, &domain)).await?;
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path = format!("{}?{}", path, query);
+        }
+        if !robots.is_allowed(&path) {
+            return Ok(Access::Disallowed);
+        }
+        let permit = DomainPermit {
+            domain,
+            interval: match robots.crawl_delay {
+                Some(crawl_delay) => std::cmp::max(self.min_interval, crawl_delay),
+                None => self.min_interval,
+            },
+            _permit: permit,
+        };
+        let deferral = permit
+            .domain
+            .next_request_at
+            .lock()
+            .await
+            .saturating_duration_since(Instant::now());
+        if deferral > Duration::from_secs(MAX_WAIT_SECS) {
+            return Ok(Access::Deferred(deferral));
+        }
+        self.wait_turn(&permit).await;
+        Ok(Access::Allowed(permit))
+    }
+
+    // Every request after the first one under the same permit, e.g. rendering the page by a browser, waits its turn again
+    pub(crate) async fn wait_turn(&self, permit: &DomainPermit) {
+        wait_turn(&permit.domain, permit.interval).await;
+    }
+
+    pub(crate) async fn get(&self, permit: &DomainPermit, url: &Url) -> Result<Response> {
+        let response = self.client.get(url.clone()).send().await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            let retry_after = response.headers().get(RETRY_AFTER).and_then(|v| v.to_str().ok());
+            if let Some(retry_after) = retry_after.and_then(parse_retry_after) {
+                let retry_at = Instant::now() + std::cmp::min(retry_after, Duration::from_secs(MAX_RETRY_AFTER_SECS));
+                let mut next_request_at = permit.domain.next_request_at.lock().await;
+                *next_request_at = std::cmp::max(*next_request_at, retry_at);
+            }
+        }
+        Ok(response)
+    }
+
+    async fn get_robots(&self, url: &Url, domain: &Domain) -> Result<Robots> {
+        let robots_url = url.join("/robots.txt")?;
+        wait_turn(domain, self.min_interval).await;
+        // A missing or unreachable robots.txt doesn't restrict anything
+        let robots = match self.client.get(robots_url).send().await {
+            Ok(response) if response.status().is_success() => match response.text().await {
+                Ok(text) => Robots::parse(&text, &self.user_agent),
+                Err(_) => Robots::default(),
+            },
+            _ => Robots::default(),
+        };
+        Ok(robots)
+    }
+}
+
+async fn wait_turn(domain: &Domain, interval: Duration) {
+    let request_at = {
+        let mut next_request_at = domain.next_request_at.lock().await;
+        let request_at = std::cmp::max(*next_request_at, Instant::now());
+        *next_request_at = request_at + interval;
+        request_at
+    };
+    tokio::time::sleep_until(request_at.into()).await;
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+    let secs = retry_at.timestamp() - chrono::Local::now().timestamp();
+    Some(Duration::from_secs(std::cmp::max(secs, 0) as u64))
+}
+
+// Telling registered domains apart properly needs the Public Suffix List,
+// but the common country-code second-level domains cover most of the links on Hacker News.
+fn registered_domain(host: &str) -> String {
+    if host.parse::<IpAddr>().is_ok() {
+        return host.to_string();
+    }
+    let labels = host.trim_end_matches('.').split('.').collect::<Vec<&str>>();
+    if labels.len() <= 2 {
+        return labels.join(".");
+    }
+    let suffix = labels[labels.len() - 2..].join(".");
+    let labels_num = if SECOND_LEVEL_SUFFIXES.contains(&suffix.as_str()) {
+        3
+    } else {
+        2
+    };
+    labels[labels.len().saturating_sub(labels_num)..].join(".")
+}
+
+// See: https://www.rfc-editor.org/rfc/rfc9309
+#[derive(Default)]
+struct Robots {
+    // (is_allowed, path pattern)
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<Duration>,
+}
+
+#[derive(Default)]
+struct RobotsGroup {
+    user_agents: Vec<String>,
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    fn parse(text: &str, user_agent: &str) -> Self {
+        // Groups are matched by the product token, e.g. "newswaters" of "newswaters/0.3"
+        let product = user_agent.split('/').next().unwrap_or("").trim().to_lowercase();
+        let mut groups: Vec<RobotsGroup> = vec![];
+        let mut is_user_agent_last = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            if key == "user-agent" {
+                // Consecutive user-agent lines share the rules that follow them
+                if !is_user_agent_last || groups.is_empty() {
+                    groups.push(RobotsGroup::default());
+                }
+                if let Some(group) = groups.last_mut() {
+                    group.user_agents.push(value.to_lowercase());
+                }
+                is_user_agent_last = true;
+                continue;
+            }
+            is_user_agent_last = false;
+            let group = match groups.last_mut() {
+                Some(group) => group,
+                None => continue,
+            };
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => group.rules.push((key == "allow", value.to_string())),
+                "crawl-delay" => {
+                    group.crawl_delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|d| d.is_finite() && *d >= 0.0)
+                        .map(Duration::from_secs_f64)
+                }
+                _ => {}
+            }
+        }
+        let is_matched = |user_agent: &str| groups.iter().any(|g| g.user_agents.iter().any(|a| a == user_agent));
+        let user_agent = if !product.is_empty() && is_matched(&product) {
+            product
+        } else {
+            "*".to_string()
+        };
+        let mut robots = Robots::default();
+        for group in groups.into_iter().filter(|g| g.user_agents.contains(&user_agent)) {
+            robots.rules.extend(group.rules);
+            robots.crawl_delay = std::cmp::max(robots.crawl_delay, group.crawl_delay);
+        }
+        robots
+    }
+
+    // The most specific, i.e. the longest, matching rule wins, and "Allow" wins a tie
+    fn is_allowed(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| matches_path(pattern, path))
+            .max_by_key(|(is_allowed, pattern)| (pattern.len(), *is_allowed))
+            .map(|(is_allowed, _)| *is_allowed)
+            .unwrap_or(true)
+    }
+}
+
+// Patterns may contain "*" for any sequence of characters, and end with "$" to match the end of the path
+fn matches_path(pattern: &str, path: &str) -> bool {
+    let (pattern, is_anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first_part = parts.next().unwrap_or("");
+    let mut rest = match path.strip_prefix(first_part) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts = parts.collect::<Vec<&str>>();
+    for (i, part) in parts.iter().enumerate() {
+        if is_anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    !is_anchored || rest.is_empty()
+}
+
+const MAX_WAIT_SECS: u64 = 60;
+
+const MAX_REDIRECTS_NUM: usize = 10;
+
+const MAX_RETRY_AFTER_SECS: u64 = 3600;
+
+const SECOND_LEVEL_SUFFIXES: [&str; 20] = [
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "co.jp", "ne.jp", "or.jp", "ac.jp", "com.au", "net.au", "org.au", "edu.au",
+    "com.br", "com.cn", "co.in", "co.kr", "co.nz", "com.tw", "com.hk", "co.za",
+];
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{
+        extract::State,
+        http::{header::HOST, HeaderMap, StatusCode},
+        response::Redirect,
+        routing, Router,
+    };
+
+    use super::*;
+
+    const ROBOTS: &str = "User-agent: *\nDisallow: /private\n\nUser-agent: newswaters\nDisallow: /\nAllow: /news\n";
+
+    #[test]
+    fn matches_robots_rules_for_product() {
+        let robots = Robots::parse(ROBOTS, "newswaters/0.3.1");
+        assert!(robots.is_allowed("/news/1"));
+        assert!(!robots.is_allowed("/private"));
+        assert!(!robots.is_allowed("/about"));
+        let robots = Robots::parse(ROBOTS, "otherbot/1.0");
+        assert!(robots.is_allowed("/about"));
+        assert!(!robots.is_allowed("/private/1"));
+    }
+
+    #[test]
+    fn matches_longest_robots_rule() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /a\nAllow: /a/b\nDisallow: /a/b/c\n",
+            "newswaters",
+        );
+        assert!(!robots.is_allowed("/a"));
+        assert!(robots.is_allowed("/a/b"));
+        assert!(!robots.is_allowed("/a/b/c"));
+        // "Allow" wins a tie
+        let robots = Robots::parse("User-agent: *\nDisallow: /a\nAllow: /a\n", "newswaters");
+        assert!(robots.is_allowed("/a"));
+    }
+
+    #[test]
+    fn matches_path_patterns() {
+        assert!(matches_path("/", "/anything"));
+        assert!(matches_path("/*.pdf", "/docs/paper.pdf"));
+        assert!(matches_path("/*.pdf", "/docs/paper.pdf?download=1"));
+        assert!(matches_path("/*.pdf$", "/docs/paper.pdf"));
+        assert!(!matches_path("/*.pdf$", "/docs/paper.pdf?download=1"));
+        assert!(matches_path("/news$", "/news"));
+        assert!(!matches_path("/news$", "/news/1"));
+        assert!(matches_path("/a*b*c", "/a-b-c-d"));
+        assert!(!matches_path("/a*b*c", "/a-c-b"));
+        assert!(!matches_path("/news", "/about/news"));
+    }
+
+    #[test]
+    fn parses_crawl_delay() {
+        let robots = Robots::parse("User-agent: *\nCrawl-delay: 2.5\n", "newswaters");
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(2500)));
+        let robots = Robots::parse("User-agent: *\nCrawl-delay: -1\n", "newswaters");
+        assert_eq!(robots.crawl_delay, None);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+        let retry_at = chrono::Utc::now() + chrono::Duration::seconds(600);
+        let retry_after = parse_retry_after(&retry_at.to_rfc2822()).unwrap();
+        assert!(Duration::from_secs(590) <= retry_after && retry_after <= Duration::from_secs(600));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn finds_registered_domain() {
+        assert_eq!(registered_domain("gist.github.com"), "github.com");
+        assert_eq!(registered_domain("www.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(registered_domain("example.com"), "example.com");
+        assert_eq!(registered_domain("127.0.0.1"), "127.0.0.1");
+    }
+
+    #[derive(Clone, Default)]
+    struct Counts {
+        robots: Arc<AtomicUsize>,
+        pages: Arc<AtomicUsize>,
+    }
+
+    // Serves a website on a local port, counting the requests to its robots.txt and its pages
+    async fn serve(counts: Counts) -> Url {
+        let app = Router::new()
+            .route(
+                "/robots.txt",
+                routing::get(|State(counts): State<Counts>| async move {
+                    counts.robots.fetch_add(1, Ordering::SeqCst);
+                    ROBOTS
+                }),
+            )
+            .route(
+                "/news/old",
+                routing::get(|| async { Redirect::temporary("/news/moved") }),
+            )
+            // "localhost" is another origin than "127.0.0.1"
+            .route(
+                "/news/away",
+                routing::get(|headers: HeaderMap| async move {
+                    let host = headers
+                        .get(HOST)
+                        .and_then(|h| h.to_str().ok())
+                        .unwrap_or("")
+                        .to_string();
+                    Redirect::temporary(&host.replace("127.0.0.1", "http://localhost"))
+                }),
+            )
+            .route(
+                "/news/busy",
+                routing::get(|State(counts): State<Counts>| async move {
+                    counts.pages.fetch_add(1, Ordering::SeqCst);
+                    (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "120")], "")
+                }),
+            )
+            .fallback(|State(counts): State<Counts>| async move {
+                counts.pages.fetch_add(1, Ordering::SeqCst);
+                "<html><body>News</body></html>"
+            })
+            .with_state(counts);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service());
+        tokio::spawn(server);
+        url
+    }
+
+    async fn get(politeness: &Politeness, url: &Url) -> Option<Response> {
+        match politeness.request_access(url).await.unwrap() {
+            Access::Allowed(permit) => Some(politeness.get(&permit, url).await.unwrap()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_robots_once_and_skips_disallowed_pages() {
+        let counts = Counts::default();
+        let url = serve(counts.clone()).await;
+        let politeness = Politeness::new("newswaters/0.3.1", 2, 100.0).unwrap();
+        assert!(get(&politeness, &url.join("/news/1").unwrap()).await.is_some());
+        assert!(get(&politeness, &url.join("/news/2").unwrap()).await.is_some());
+        assert!(get(&politeness, &url.join("/about").unwrap()).await.is_none());
+        assert_eq!(counts.robots.load(Ordering::SeqCst), 1);
+        assert_eq!(counts.pages.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn throttles_requests_to_domain() {
+        let counts = Counts::default();
+        let url = serve(counts.clone()).await;
+        let politeness = Politeness::new("newswaters/0.3.1", 2, 10.0).unwrap();
+        let start_time = Instant::now();
+        for i in 0..3 {
+            get(&politeness, &url.join(&format!("/news/{}", i)).unwrap())
+                .await
+                .unwrap();
+        }
+        // robots.txt and 3 pages, each 100 ms after the previous one
+        assert!(start_time.elapsed() >= Duration::from_millis(300));
+        assert_eq!(counts.pages.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn defers_domain_after_retry_after() {
+        let counts = Counts::default();
+        let url = serve(counts.clone()).await;
+        let politeness = Politeness::new("newswaters/0.3.1", 2, 100.0).unwrap();
+        let response = get(&politeness, &url.join("/news/busy").unwrap()).await;
+        assert!(response.is_some());
+        match politeness.request_access(&url.join("/news/1").unwrap()).await.unwrap() {
+            Access::Deferred(deferral) => assert!(deferral > Duration::from_secs(MAX_WAIT_SECS)),
+            _ => panic!("Domain isn't deferred"),
+        }
+        assert_eq!(counts.pages.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_within_origin() {
+        let counts = Counts::default();
+        let url = serve(counts.clone()).await;
+        let politeness = Politeness::new("newswaters/0.3.1", 2, 100.0).unwrap();
+        let response = get(&politeness, &url.join("/news/old").unwrap()).await.unwrap();
+        assert_eq!(response.url().path(), "/news/moved");
+        let response = get(&politeness, &url.join("/news/away").unwrap()).await.unwrap();
+        assert!(response.status().is_redirection());
+        assert_eq!(counts.pages.load(Ordering::SeqCst), 1);
+    }
+}
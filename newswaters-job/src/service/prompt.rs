@@ -0,0 +1,88 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+
+// A prompt is read from "<prompts dir>/<id>/v<version>.txt", where the latest version is the current one.
+// Changing a prompt means adding its next version, so that the texts generated by the older ones can be told apart.
+pub(crate) struct Template {
+    pub id: String,
+    pub version: u32,
+    text: String,
+}
+
+impl Template {
+    // Recorded along with the generated texts, e.g. "summary@v2"
+    pub(crate) fn key(&self) -> String {
+        format!("{}@v{}", self.id, self.version)
+    }
+
+    // Replaces the named variables, e.g. "{title}", in a single pass, so that the values are never rendered themselves
+    pub(crate) fn render(&self, variables: &[(&str, &str)]) -> Result<String> {
+        let mut rendered = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let name = match rest[1..].find('}') {
+                Some(end) => &rest[1..end + 1],
+                None => break,
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                rendered.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+            match variables.iter().find(|(n, _)| *n == name) {
+                Some((_, value)) => rendered.push_str(value),
+                None => bail!("Prompt {} has an unknown variable: {}", self.key(), name),
+            }
+            rest = &rest[name.len() + 2..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+
+    // The sentences without variables, which a completion echoing the prompt repeats
+    pub(crate) fn fixed_phrases(&self) -> Vec<&str> {
+        self.text
+            .split(['\n', '.'])
+            .map(str::trim)
+            .filter(|p| p.len() >= MIN_FIXED_PHRASE_LEN && !p.contains('{'))
+            .collect()
+    }
+}
+
+const MIN_FIXED_PHRASE_LEN: usize = 16;
+
+pub(crate) fn find_template(prompts_dir: &Path, id: &str) -> Result<Template> {
+    let dir = prompts_dir.join(id);
+    let mut latest_version = None;
+    for entry in fs::read_dir(&dir)? {
+        let file_name = entry?.file_name();
+        let version = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix('v'))
+            .and_then(|n| n.strip_suffix(".txt"))
+            .and_then(|n| n.parse::<u32>().ok());
+        latest_version = std::cmp::max(latest_version, version);
+    }
+    let version = match latest_version {
+        Some(version) => version,
+        None => bail!("Prompt has no versions: {}", dir.display()),
+    };
+    let text = fs::read_to_string(dir.join(format!("v{}.txt", version)))?;
+    Ok(Template {
+        id: id.to_string(),
+        version,
+        text,
+    })
+}
+
+// The keys of the current versions, which the recorded keys are compared with to find outdated texts
+pub(crate) fn find_keys(prompts_dir: &Path, ids: &[&str]) -> Result<Vec<String>> {
+    let mut keys = vec![];
+    for id in ids {
+        keys.push(find_template(prompts_dir, id)?.key());
+    }
+    Ok(keys)
+}
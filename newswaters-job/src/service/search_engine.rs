@@ -55,3 +55,21 @@ pub(crate) async fn upsert(collection_name: String, id: i32, embedding: Vec<f32>
     client.post(endpoint).json(&payload).send().await?;
     Ok(())
 }
+
+#[derive(Serialize)]
+struct DeleteRequest {
+    collection_name: String,
+    ids: Vec<i32>,
+}
+
+pub(crate) async fn delete(collection_name: String, ids: Vec<i32>) -> Result<()> {
+    let payload = DeleteRequest { collection_name, ids };
+    let client = reqwest::Client::new();
+    let endpoint = format!(
+        "http://{}:{}/delete",
+        env::var("SEARCH_ENGINE_HOST")?,
+        env::var("SEARCH_ENGINE_PORT")?
+    );
+    client.post(endpoint).json(&payload).send().await?.error_for_status()?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use super::{prompt::Template, Summary};
+
+// The bounds a summary must stay within, which are given by the command
+pub(crate) struct SummaryChecks {
+    pub min_len: usize,
+    pub max_len: usize,
+    pub min_english_rate: f64,
+    pub min_source_overlap: f64,
+}
+
+// Rejects the summaries which are no summaries at all, e.g. the prompt echoed back, a refusal or a loop of the same
+// sentence, or which have little to do with their source, i.e. the text or the partial summaries they summarize.
+pub(crate) fn check_summary(
+    summary: &Summary,
+    source: &str,
+    template: &Template,
+    checks: &SummaryChecks,
+) -> Result<()> {
+    let text = summary.summary.as_str();
+    let lowercase_text = text.to_lowercase();
+    let len = text.chars().count();
+    if len < checks.min_len || checks.max_len < len {
+        bail!("Summary is out of the length bounds: {}", len);
+    }
+    if let Some(phrase) = template
+        .fixed_phrases()
+        .into_iter()
+        .chain(ECHO_MARKERS)
+        .find(|p| lowercase_text.contains(&p.to_lowercase()))
+    {
+        bail!("Summary echoes the prompt: {}", phrase);
+    }
+    if let Some(phrase) = REFUSAL_PHRASES.iter().find(|p| lowercase_text.contains(*p)) {
+        bail!("Summary is a refusal: {}", phrase);
+    }
+    if let Some(sentence) = find_repeated_sentence(&lowercase_text) {
+        bail!("Summary repeats itself: {}", sentence);
+    }
+    let english_rate = english_rate(&lowercase_text);
+    if english_rate < checks.min_english_rate {
+        bail!("Summary doesn't look like English: english_rate={:.2}", english_rate);
+    }
+    // Only the source can be trusted, as the topics are generated along with the summary
+    let source_overlap = source_overlap(&lowercase_text, &source.to_lowercase());
+    if source_overlap < checks.min_source_overlap {
+        bail!(
+            "Summary has little in common with its source: source_overlap={:.2}",
+            source_overlap
+        );
+    }
+    Ok(())
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|w| !w.is_empty())
+}
+
+// Rambling completions tend to loop over the same sentence
+fn find_repeated_sentence(text: &str) -> Option<&str> {
+    let mut counts = HashMap::new();
+    for sentence in text
+        .split(['.', '!', '?', '\n'])
+        .map(str::trim)
+        .filter(|s| s.len() >= MIN_REPEATED_SENTENCE_LEN)
+    {
+        let count = counts.entry(sentence).or_insert(0);
+        *count += 1;
+        if *count >= MAX_SENTENCE_REPETITIONS_NUM {
+            return Some(sentence);
+        }
+    }
+    None
+}
+
+// The rate of the most common English words, which is far lower in any other language
+fn english_rate(text: &str) -> f64 {
+    let (mut words_num, mut english_words_num) = (0, 0);
+    for word in words(text) {
+        words_num += 1;
+        if ENGLISH_STOPWORDS.contains(&word) {
+            english_words_num += 1;
+        }
+    }
+    if words_num == 0 {
+        return 0.0;
+    }
+    english_words_num as f64 / words_num as f64
+}
+
+// The rate of the content words, i.e. the long enough ones, which also appear in the source
+fn source_overlap(text: &str, source: &str) -> f64 {
+    let source_words = words(source).collect::<HashSet<&str>>();
+    let content_words = words(text)
+        .filter(|w| w.chars().count() >= MIN_CONTENT_WORD_LEN)
+        .collect::<Vec<&str>>();
+    if content_words.is_empty() {
+        return 0.0;
+    }
+    let overlapping_words_num = content_words.iter().filter(|w| source_words.contains(*w)).count();
+    overlapping_words_num as f64 / content_words.len() as f64
+}
+
+const MIN_REPEATED_SENTENCE_LEN: usize = 20;
+
+const MAX_SENTENCE_REPETITIONS_NUM: usize = 3;
+
+const MIN_CONTENT_WORD_LEN: usize = 5;
+
+// Left in the completion by the instruct template of the inference server
+const ECHO_MARKERS: [&str; 3] = ["[INST]", "[/INST]", "<s>"];
+
+const REFUSAL_PHRASES: [&str; 12] = [
+    "as an ai",
+    "as a language model",
+    "i'm sorry",
+    "i am sorry",
+    "i apologize",
+    "i cannot",
+    "i can't",
+    "i'm unable",
+    "i am unable",
+    "i'm not able",
+    "no content was provided",
+    "unable to summarize",
+];
+
+const ENGLISH_STOPWORDS: [&str; 40] = [
+    "the", "of", "and", "to", "a", "in", "is", "it", "that", "for", "on", "with", "as", "was", "are", "be", "by",
+    "this", "an", "at", "from", "or", "which", "its", "their", "has", "have", "not", "but", "can", "how", "they",
+    "also", "about", "such", "these", "into", "more", "other", "we",
+];
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::service::prompt;
+
+    const SOURCE: &str = "The Rust compiler now caches the results of incremental builds across machines. \
+        Teams sharing a remote cache report that their continuous integration pipelines finish three times faster, \
+        although the cache has to be invalidated whenever the toolchain version changes.";
+
+    const SUMMARY: &str = "The Rust compiler can share incremental builds through a remote cache, which makes \
+        continuous integration pipelines finish three times faster, as long as the toolchain version is the same.";
+
+    fn checks() -> SummaryChecks {
+        SummaryChecks {
+            min_len: 80,
+            max_len: 6000,
+            min_english_rate: 0.15,
+            min_source_overlap: 0.3,
+        }
+    }
+
+    fn check(summary: &str, source: &str) -> Result<()> {
+        let summary = Summary {
+            topics: vec!["Rust".to_string()],
+            summary: summary.to_string(),
+        };
+        check_summary(
+            &summary,
+            source,
+            &prompt::find_template(Path::new("prompts"), "summary")?,
+            &checks(),
+        )
+    }
+
+    #[test]
+    fn accepts_summary() {
+        assert!(check(SUMMARY, SOURCE).is_ok());
+    }
+
+    #[test]
+    fn rejects_summary_out_of_length_bounds() {
+        assert!(check("Rust builds are faster.", SOURCE)
+            .unwrap_err()
+            .to_string()
+            .contains("length"));
+    }
+
+    #[test]
+    fn rejects_echoed_prompt() {
+        let summary = format!("{SUMMARY} Don't make up information if it's not provided.");
+        assert!(check(&summary, SOURCE).unwrap_err().to_string().contains("echoes"));
+        let summary = format!("[INST] {SUMMARY}");
+        assert!(check(&summary, SOURCE).unwrap_err().to_string().contains("echoes"));
+    }
+
+    #[test]
+    fn rejects_refusal() {
+        let summary = format!("I'm sorry, but the content is not enough. {SUMMARY}");
+        assert!(check(&summary, SOURCE).unwrap_err().to_string().contains("refusal"));
+    }
+
+    #[test]
+    fn finds_repeated_sentence() {
+        let text =
+            "the cache is shared by the teams. the cache is shared by the teams. the cache is shared by the teams.";
+        assert_eq!(find_repeated_sentence(text), Some("the cache is shared by the teams"));
+        let text = "the cache is shared by the teams. the cache is shared by the teams. builds are faster now.";
+        assert_eq!(find_repeated_sentence(text), None);
+        // Short sentences, e.g. list items, are allowed to repeat
+        assert_eq!(find_repeated_sentence("yes. yes. yes. yes."), None);
+    }
+
+    #[test]
+    fn computes_english_rate() {
+        assert!(english_rate(&SUMMARY.to_lowercase()) >= 0.15);
+        let text = "der rust compiler speichert die ergebnisse inkrementeller builds zwischen rechnern";
+        assert!(english_rate(text) < 0.15);
+        assert_eq!(english_rate(""), 0.0);
+    }
+
+    #[test]
+    fn computes_source_overlap() {
+        assert!(source_overlap(&SUMMARY.to_lowercase(), &SOURCE.to_lowercase()) >= 0.3);
+        let text = "python notebooks render interactive charts inside browsers";
+        assert!(source_overlap(text, &SOURCE.to_lowercase()) < 0.3);
+        // Only the words of at least `MIN_CONTENT_WORD_LEN` chars are counted
+        assert_eq!(source_overlap("it is so", "it is so"), 0.0);
+    }
+}
@@ -45,6 +45,7 @@ async fn main() -> Result<()> {
         .route("/healthz", routing::get(|| async { "Ok" }))
         .route("/find-missing", routing::post(find_missing))
         .route("/upsert", routing::post(upsert))
+        .route("/delete", routing::post(delete))
         .route("/search-similar", routing::post(search_similar))
         .with_state(state);
     let port = env::var("SEARCH_ENGINE_PORT").unwrap_or("3000".to_string());
@@ -107,6 +108,24 @@ async fn upsert(
     Ok(Json(response))
 }
 
+#[derive(Deserialize)]
+struct DeleteRequest {
+    collection_name: String,
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct DeleteResponse {}
+
+async fn delete(
+    State(state): State<AppState>,
+    Json(payload): Json<DeleteRequest>,
+) -> Result<Json<DeleteResponse>, AppError> {
+    state.vector_repo.delete(payload.collection_name, payload.ids).await?;
+    let response = DeleteResponse {};
+    Ok(Json(response))
+}
+
 #[derive(Deserialize)]
 struct SearchSimilarRequest {
     collection_name: String,
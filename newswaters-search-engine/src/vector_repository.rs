@@ -4,8 +4,8 @@ use anyhow::Result;
 use qdrant_client::{
     prelude::{Payload, QdrantClient},
     qdrant::{
-        point_id::PointIdOptions, vectors_config::Config, CreateCollection, Distance, PointId, PointStruct,
-        SearchPoints, VectorParams, VectorsConfig,
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf, vectors_config::Config, CreateCollection,
+        Distance, PointId, PointStruct, PointsIdsList, PointsSelector, SearchPoints, VectorParams, VectorsConfig,
     },
 };
 
@@ -82,6 +82,18 @@ impl VectorRepository {
         Ok(())
     }
 
+    pub(crate) async fn delete(&self, collection_name: String, ids: Vec<i32>) -> Result<()> {
+        let points = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                ids: ids.iter().map(|i| (*i as u64).into()).collect::<Vec<PointId>>(),
+            })),
+        };
+        self.client
+            .delete_points_blocking(collection_name, &points, None)
+            .await?;
+        Ok(())
+    }
+
     pub(crate) async fn search_similar(
         &self,
         collection_name: String,